@@ -0,0 +1,76 @@
+//! Throughput of concurrent inserts and reads.
+//!
+//! Run with `cargo bench --bench insert`, optionally followed by `--` and a
+//! filter on the benchmark names.
+//!
+//! Each benchmark runs with a short time-to-live, so that the expiration task
+//! removes entries while they are inserted, and with a long time-to-live.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_ttl::{config::AsyncTtlConfig, AsyncTtl, CacheEntry};
+
+type Cache = AsyncTtl<HashMap<u64, CacheEntry<u64>>, u64, u64>;
+
+const TASKS: u64 = 8;
+const OPERATIONS: u64 = 100_000;
+const TTLS: [Duration; 2] = [Duration::from_millis(100), Duration::from_secs(3600)];
+
+/// Runs `TASKS` concurrent tasks, each calling `operation` `OPERATIONS` times,
+/// and prints the number of operations per second.
+async fn bench<F, Fut>(name: &str, operation: F)
+where
+    F: Fn(Arc<Cache>, u64) -> Fut + Copy + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    if filter.is_some_and(|filter| !name.contains(&filter)) {
+        return;
+    }
+
+    for ttl in TTLS {
+        let cache = Cache::spawn(AsyncTtlConfig::new(ttl));
+        let start = Instant::now();
+
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|task| {
+                let cache = cache.as_arc().clone();
+                tokio::spawn(async move {
+                    for i in 0..OPERATIONS {
+                        operation(cache.clone(), task * OPERATIONS + i).await;
+                    }
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let elapsed = start.elapsed();
+        let throughput = (TASKS * OPERATIONS) as f64 / elapsed.as_secs_f64();
+        println!("{name:<12} ttl={ttl:<8?} {elapsed:>10.2?} {throughput:>10.0} ops/s");
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    bench("insert", |cache, key| async move {
+        cache.insert(key, key).await;
+    })
+    .await;
+
+    bench("insert_get", |cache, key| async move {
+        if key % 2 == 0 {
+            cache.insert(key, key).await;
+        } else {
+            cache.get_cloned(&(key - 1)).await;
+        }
+    })
+    .await;
+}
@@ -0,0 +1,354 @@
+use std::{
+    fmt,
+    future::Future,
+    hash::Hash,
+    marker::PhantomData,
+    sync::{atomic::AtomicU64, Arc, OnceLock},
+};
+
+use tokio::{
+    runtime::Handle,
+    sync::{Mutex, Notify},
+};
+
+use crate::{
+    config::AsyncTtlConfig,
+    events::DEFAULT_EVENT_CAPACITY,
+    listener::{EvictionListener, DEFAULT_LISTENER_CONCURRENCY},
+    loader::Loaders,
+    locked::Garbage,
+    queue,
+    stats::StatsCounter,
+    AsyncTtl, AsyncTtlExpireTask, Cache, CacheEntry, CacheMap, Clock, RemovalCause,
+    ShardedAsyncTtl, ShardedCache, ShardedExpireTask, TokioClock, Weigher,
+};
+
+/// Builder for [`AsyncTtl`].
+///
+/// This builder is used to configure the components of a cache that cannot be
+/// set in its [`AsyncTtlConfig`].
+pub struct AsyncTtlBuilder<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    config: AsyncTtlConfig,
+    weigher: Option<Box<dyn Weigher<K, V> + Send + Sync>>,
+    listener: Option<EvictionListener<K, V>>,
+    listener_concurrency: usize,
+    event_capacity: usize,
+    background_drop: Option<fn(Garbage<T, V>)>,
+    clock: Arc<dyn Clock>,
+    _map: PhantomData<T>,
+}
+
+impl<T, K, V> AsyncTtlBuilder<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    pub(crate) fn new(config: AsyncTtlConfig) -> Self {
+        Self {
+            config,
+            weigher: None,
+            listener: None,
+            listener_concurrency: DEFAULT_LISTENER_CONCURRENCY,
+            event_capacity: DEFAULT_EVENT_CAPACITY,
+            background_drop: None,
+            clock: Arc::new(TokioClock),
+            _map: PhantomData,
+        }
+    }
+
+    /// Sets the [`Weigher`] used to compute the weight of entries.
+    ///
+    /// Without weigher, each entry has a weight of 1.
+    pub fn weigher(mut self, weigher: impl Weigher<K, V> + Send + Sync + 'static) -> Self {
+        self.weigher = Some(Box::new(weigher));
+
+        self
+    }
+
+    /// Sets a listener called when entries are removed from the cache.
+    ///
+    /// The listener receives the key and the value of each removed entry, with
+    /// the [`RemovalCause`] of the removal. It is called after the locks of the
+    /// cache have been released, so it may access the cache.
+    ///
+    /// Values of explicitly removed entries are cloned, since they are also
+    /// returned to the caller. This replaces any previously set listener.
+    pub fn eviction_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+        V: Clone,
+    {
+        self.listener = Some(EvictionListener::new(listener));
+
+        self
+    }
+
+    /// Sets an asynchronous listener called when entries are removed from the
+    /// cache.
+    ///
+    /// This method behaves like [`eviction_listener`], but the listener
+    /// returns a future. Removed entries are collected while the cache is
+    /// locked, and the futures are awaited once the locks have been released
+    /// by the operation that removed the entries. At most
+    /// [`listener_concurrency`] futures are awaited concurrently.
+    ///
+    /// The futures are run by a task spawned on the current runtime, so the
+    /// listener is still called for all removed entries if the operation is
+    /// cancelled, for example by a timeout.
+    ///
+    /// [`eviction_listener`]: Self::eviction_listener
+    /// [`listener_concurrency`]: Self::listener_concurrency
+    pub fn async_eviction_listener<F, Fut>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+        V: Clone,
+    {
+        self.listener = Some(EvictionListener::new_async(listener));
+
+        self
+    }
+
+    /// Sets the maximum number of concurrent calls to the async eviction
+    /// listener.
+    ///
+    /// Defaults to 16.
+    pub fn listener_concurrency(mut self, listener_concurrency: usize) -> Self {
+        self.listener_concurrency = listener_concurrency;
+
+        self
+    }
+
+    /// Sets the capacity of the channel used to send events to subscribers of
+    /// [`AsyncTtl::subscribe`].
+    ///
+    /// Subscribers that fall behind by more events than this capacity miss
+    /// the oldest events. Defaults to 1024.
+    ///
+    /// [`AsyncTtl::subscribe`]: crate::AsyncTtl::subscribe
+    pub fn event_capacity(mut self, event_capacity: usize) -> Self {
+        self.event_capacity = event_capacity;
+
+        self
+    }
+
+    /// Sets the [`Clock`] used as the source of time of the cache.
+    ///
+    /// Defaults to [`TokioClock`]. A [`ManualClock`] can be used to test the
+    /// expiration of entries without waiting.
+    ///
+    /// [`ManualClock`]: crate::ManualClock
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+
+        self
+    }
+
+    /// Drops the values removed from the cache on the blocking threads of the
+    /// [tokio] runtime.
+    ///
+    /// Removed values are always dropped after the locks of the cache have
+    /// been released. With this option, they are dropped with
+    /// [`spawn_blocking`] instead of by the task that removed them, which is
+    /// useful for values that are expensive to drop. Values removed outside
+    /// of a runtime are dropped in place, and values sent to the eviction
+    /// listener are dropped by the listener.
+    ///
+    /// [`spawn_blocking`]: tokio::task::spawn_blocking
+    pub fn drop_in_background(mut self) -> Self
+    where
+        T: Send + 'static,
+        V: Send + 'static,
+    {
+        self.background_drop = Some(|garbage| match Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(move || drop(garbage));
+            }
+            Err(_) => drop(garbage),
+        });
+
+        self
+    }
+
+    /// Initialize the configured [`AsyncTtl`] cache.
+    ///
+    /// This method returns the cache wrapped in an [`Arc`] and the expiration
+    /// task.
+    #[allow(clippy::type_complexity)]
+    pub fn build(self) -> (Arc<AsyncTtl<T, K, V>>, AsyncTtlExpireTask<T, K, V>) {
+        let cache = Arc::new(self.build_shards(1).swap_remove(0));
+
+        (cache.clone(), AsyncTtlExpireTask::new(cache))
+    }
+
+    /// Initialize the configured cache as a [`ShardedAsyncTtl`] with the given
+    /// number of shards.
+    ///
+    /// This method returns the cache wrapped in an [`Arc`] and the expiration
+    /// task, which expires the entries of all the shards. See
+    /// [`ShardedAsyncTtl::new`].
+    #[allow(clippy::type_complexity)]
+    pub fn build_sharded(
+        self,
+        shards: usize,
+    ) -> (Arc<ShardedAsyncTtl<T, K, V>>, ShardedExpireTask<T, K, V>)
+    where
+        K: Hash,
+    {
+        let cache = Arc::new(ShardedAsyncTtl::from_shards(self.build_shards(shards)));
+
+        (cache.clone(), ShardedExpireTask::new(cache))
+    }
+
+    /// Initialize the given number of cache shards, which share the weigher,
+    /// listener, events and statistics of the cache.
+    ///
+    /// The maximum capacity and weight of the cache are split between the
+    /// shards, so that the limits of the shards add up to them. The number of
+    /// shards is reduced to the maximum capacity and weight if they are
+    /// lower, so that each shard can hold an entry.
+    fn build_shards(mut self, shards: usize) -> Vec<AsyncTtl<T, K, V>> {
+        if let Some(listener) = &mut self.listener {
+            listener.set_concurrency(self.listener_concurrency);
+        }
+
+        let max_weight = self
+            .config
+            .max_weight
+            .map(|max_weight| usize::try_from(max_weight).unwrap_or(usize::MAX));
+        let shards = [self.config.max_capacity, max_weight]
+            .into_iter()
+            .flatten()
+            .fold(shards, usize::min)
+            .max(1);
+
+        let weigher: Option<Arc<dyn Weigher<K, V> + Send + Sync>> = self.weigher.map(Arc::from);
+        let listener = self.listener.map(Arc::new);
+        let events = Arc::new(OnceLock::new());
+        let stats = Arc::new(StatsCounter::default());
+        let wakeup = Arc::new(Notify::new());
+
+        (0..shards)
+            .map(|index| {
+                let config = AsyncTtlConfig {
+                    max_capacity: self.config.max_capacity.map(|max_capacity| {
+                        shard_limit(max_capacity as u64, shards, index) as usize
+                    }),
+                    max_weight: self
+                        .config
+                        .max_weight
+                        .map(|max_weight| shard_limit(max_weight, shards, index)),
+                    ..self.config
+                };
+                let (expirations, expires) = queue::channel(self.clock.now());
+
+                AsyncTtl {
+                    expires: Mutex::new(expires),
+                    expirations,
+                    data: Default::default(),
+                    access: Default::default(),
+                    ticks: AtomicU64::new(0),
+                    weight: AtomicU64::new(0),
+                    config,
+                    weigher: weigher.clone(),
+                    listener: listener.clone(),
+                    events: events.clone(),
+                    event_capacity: self.event_capacity,
+                    loaders: Loaders::default(),
+                    stats: stats.clone(),
+                    background_drop: self.background_drop,
+                    clock: self.clock.clone(),
+                    wakeup: wakeup.clone(),
+                    _value: PhantomData,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Returns the share of a limit of the shard at the given index.
+///
+/// The remainder of the division is shared between the first shards, so the
+/// shares of all the shards add up to the limit.
+fn shard_limit(limit: u64, shards: usize, index: usize) -> u64 {
+    let shards = shards as u64;
+
+    limit / shards + u64::from((index as u64) < limit % shards)
+}
+
+impl<T, K, V> AsyncTtlBuilder<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default + Send + Sync + 'static,
+    K: Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    /// Initialize the configured [`AsyncTtl`] cache and spawns its expiration
+    /// task on the current [tokio] runtime.
+    ///
+    /// See [`AsyncTtl::spawn`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a [tokio] runtime.
+    pub fn spawn(self) -> Cache<T, K, V> {
+        self.spawn_on(&Handle::current())
+    }
+
+    /// Initialize the configured [`AsyncTtl`] cache and spawns its expiration
+    /// task on the runtime of the given [`Handle`].
+    ///
+    /// See [`AsyncTtl::spawn_on`].
+    pub fn spawn_on(self, handle: &Handle) -> Cache<T, K, V> {
+        let (cache, task) = self.build();
+        let shutdown = task.shutdown_handle();
+
+        Cache::spawn(cache, async move { task.run().await }, shutdown, handle)
+    }
+
+    /// Initialize the configured cache as a [`ShardedAsyncTtl`] with the given
+    /// number of shards, and spawns its expiration task on the current
+    /// [tokio] runtime.
+    ///
+    /// See [`ShardedAsyncTtl::spawn`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a [tokio] runtime.
+    pub fn spawn_sharded(self, shards: usize) -> ShardedCache<T, K, V>
+    where
+        K: Hash,
+    {
+        self.spawn_sharded_on(shards, &Handle::current())
+    }
+
+    /// Initialize the configured cache as a [`ShardedAsyncTtl`] with the given
+    /// number of shards, and spawns its expiration task on the runtime of the
+    /// given [`Handle`].
+    ///
+    /// See [`ShardedAsyncTtl::spawn_on`].
+    pub fn spawn_sharded_on(self, shards: usize, handle: &Handle) -> ShardedCache<T, K, V>
+    where
+        K: Hash,
+    {
+        let (cache, task) = self.build_sharded(shards);
+        let shutdown = task.shutdown_handle();
+
+        ShardedCache::spawn(cache, async move { task.run().await }, shutdown, handle)
+    }
+}
+
+impl<T, K, V> fmt::Debug for AsyncTtlBuilder<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncTtlBuilder")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
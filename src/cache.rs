@@ -0,0 +1,162 @@
+use std::{fmt, future::Future, ops::Deref, sync::Arc};
+
+use tokio::{runtime::Handle, task::JoinHandle};
+
+use crate::{AsyncTtl, ShardedAsyncTtl, ShutdownHandle};
+
+/// [`AsyncTtl`] cache with a running expiration task.
+///
+/// This handle is returned by [`AsyncTtl::spawn`] and [`AsyncTtl::spawn_on`].
+/// See [`CacheHandle`].
+pub type Cache<T, K, V> = CacheHandle<AsyncTtl<T, K, V>>;
+
+/// [`ShardedAsyncTtl`] cache with a running expiration task.
+///
+/// This handle is returned by [`ShardedAsyncTtl::spawn`] and
+/// [`ShardedAsyncTtl::spawn_on`]. See [`CacheHandle`].
+pub type ShardedCache<T, K, V> = CacheHandle<ShardedAsyncTtl<T, K, V>>;
+
+/// Cache with a running expiration task.
+///
+/// This handle dereferences to the cache and can be cloned cheaply. The
+/// expiration task is aborted once all the clones of the handle have been
+/// dropped. It is used through the [`Cache`] and [`ShardedCache`] aliases.
+///
+/// See the [crate] documentation to learn more.
+pub struct CacheHandle<C> {
+    cache: Arc<C>,
+    reaper: Arc<Reaper>,
+}
+
+impl<C> CacheHandle<C> {
+    /// Spawns the expiration task of the cache on the given runtime.
+    pub(crate) fn spawn(
+        cache: Arc<C>,
+        task: impl Future<Output = ()> + Send + 'static,
+        shutdown: ShutdownHandle,
+        handle: &Handle,
+    ) -> Self {
+        Self {
+            cache,
+            reaper: Arc::new(Reaper {
+                task: handle.spawn(task),
+                shutdown,
+            }),
+        }
+    }
+
+    /// Returns the underlying cache.
+    ///
+    /// The returned cache does not keep the expiration task running.
+    pub fn as_arc(&self) -> &Arc<C> {
+        &self.cache
+    }
+
+    /// Returns a handle used to shut down the expiration task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.reaper.shutdown.clone()
+    }
+}
+
+impl<C> Deref for CacheHandle<C> {
+    type Target = C;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cache
+    }
+}
+
+impl<C> Clone for CacheHandle<C> {
+    fn clone(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+            reaper: self.reaper.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for CacheHandle<C>
+where
+    C: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheHandle")
+            .field("cache", &self.cache)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Spawned expiration task, aborted when dropped.
+struct Reaper {
+    task: JoinHandle<()>,
+    shutdown: ShutdownHandle,
+}
+
+impl Drop for Reaper {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use tokio::time;
+
+    use super::*;
+    use crate::{config::AsyncTtlConfig, CacheEntry};
+
+    type TestMap = HashMap<u32, CacheEntry<u32>>;
+
+    #[tokio::test(start_paused = true)]
+    async fn spawned_task_runs_until_all_handles_are_dropped() {
+        let cache =
+            AsyncTtl::<TestMap, u32, u32>::spawn(AsyncTtlConfig::new(Duration::from_secs(1)));
+        let handle = cache.clone();
+
+        cache.insert(1, 1).await;
+        time::sleep(Duration::from_secs(2)).await;
+        assert!(cache.read().await.is_empty());
+
+        let task = cache.reaper.task.abort_handle();
+        drop(cache);
+        time::sleep(Duration::from_secs(1)).await;
+        assert!(!task.is_finished());
+
+        // The cache outlives the handles, so the task is aborted rather than
+        // stopped because the cache was dropped.
+        let cache = handle.as_arc().clone();
+        drop(handle);
+        time::sleep(Duration::from_secs(1)).await;
+        assert!(task.is_finished());
+
+        cache.insert(2, 2).await;
+        time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(cache.read().await.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sharded_cache_runs_task_until_dropped() {
+        let cache = ShardedAsyncTtl::<TestMap, u32, u32>::spawn(
+            AsyncTtlConfig::new(Duration::from_secs(1)),
+            4,
+        );
+
+        for key in 0..8 {
+            cache.insert(key, key).await;
+        }
+        time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(cache.weight(), 0);
+
+        let task = cache.reaper.task.abort_handle();
+        let shards = cache.as_arc().clone();
+        drop(cache);
+        time::sleep(Duration::from_secs(1)).await;
+        assert!(task.is_finished());
+
+        shards.insert(1, 1).await;
+        time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(shards.weight(), 1);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use tokio::{
+    sync::watch,
+    time::{self, Instant},
+};
+
+/// Source of time of an [`AsyncTtl`] cache.
+///
+/// The clock provides the current instant used to compute the expiration of
+/// entries, and is used by the expiration task to wait for the next
+/// expiration. It is set with [`AsyncTtlBuilder::clock`].
+///
+/// [`AsyncTtl`]: crate::AsyncTtl
+/// [`AsyncTtlBuilder::clock`]: crate::AsyncTtlBuilder::clock
+pub trait Clock: Send + Sync {
+    /// Returns the current instant.
+    fn now(&self) -> Instant;
+
+    /// Waits until the clock reaches the given instant.
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+/// Clock based on the [tokio] time facilities.
+///
+/// This is the default clock of caches. It follows the time of the runtime,
+/// including when it is paused.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(time::sleep_until(deadline))
+    }
+}
+
+/// Clock that only moves forward when it is advanced.
+///
+/// This clock is used to test the expiration of entries without waiting.
+/// Clones of the clock share the same time, so a clone can be kept to
+/// advance the clock of a cache. The expiration task of the cache is woken up
+/// when the clock is advanced past the next expiration.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<watch::Sender<Instant>>,
+}
+
+impl ManualClock {
+    /// Initialize a new [`ManualClock`] starting at the current instant.
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    /// Initialize a new [`ManualClock`] starting at the given instant.
+    pub fn starting_at(now: Instant) -> Self {
+        Self {
+            now: Arc::new(watch::Sender::new(now)),
+        }
+    }
+
+    /// Advances the clock by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+
+    /// Sets the clock to the given instant.
+    ///
+    /// The clock never goes backward, so instants before the current one are
+    /// ignored.
+    pub fn set(&self, instant: Instant) {
+        self.now.send_if_modified(|now| {
+            if instant <= *now {
+                return false;
+            }
+
+            *now = instant;
+            true
+        });
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.borrow()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let mut now = self.now.subscribe();
+
+        Box::pin(async move {
+            // Waiting only fails if the clock has been dropped.
+            let _ = now.wait_for(|now| *now >= deadline).await;
+        })
+    }
+}
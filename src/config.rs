@@ -4,7 +4,6 @@
 
 use std::time::Duration;
 
-const DEFAULT_EMPTY_DELAY: Duration = Duration::from_millis(100);
 const DEFAULT_DELTA_DELAY: Duration = Duration::from_millis(5);
 
 /// Configuration of an [`AsyncTtl`] cache.
@@ -12,12 +11,59 @@ const DEFAULT_DELTA_DELAY: Duration = Duration::from_millis(5);
 /// [`AsyncTtl`]: crate::AsyncTtl
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct AsyncTtlConfig {
-    /// Expiration delay of entries.
+    /// Default expiration delay of entries.
     pub expires_after: Duration,
+    /// Expiration policy of entries.
+    ///
+    /// Defaults to [`ExpirationPolicy::TimeToLive`].
+    pub policy: ExpirationPolicy,
+    /// Maximum age of entries.
+    ///
+    /// Entries expire after this delay since their insertion, regardless of
+    /// accesses. Combined with [`ExpirationPolicy::TimeToIdle`], this allow
+    /// to expire entries that are not accessed while bounding their lifetime.
+    /// With [`ExpirationPolicy::TimeToLive`], it caps custom time-to-live of
+    /// entries.
+    ///
+    /// Defaults to `None`.
+    pub max_age: Option<Duration>,
+    /// Time-to-live of errors returned by fallible loaders.
+    ///
+    /// When set, errors returned by the loader of
+    /// [`AsyncTtl::try_get_or_insert_with`] are cached for this delay, and
+    /// returned again instead of calling the loader. This is usually shorter
+    /// than the time-to-live of entries.
+    ///
+    /// Defaults to `None` (errors are not cached).
+    ///
+    /// [`AsyncTtl::try_get_or_insert_with`]: crate::AsyncTtl::try_get_or_insert_with
+    pub negative_ttl: Option<Duration>,
+    /// Maximum number of entries in the cache.
+    ///
+    /// When the cache is full, inserting a new entry evicts the least recently
+    /// used entries.
+    ///
+    /// Defaults to `None` (unbounded).
+    pub max_capacity: Option<usize>,
+    /// Maximum total weight of the entries in the cache.
+    ///
+    /// The weight of entries is computed by the [`Weigher`] of the cache, or
+    /// is 1 for each entry if the cache has no weigher. When the total weight
+    /// exceeds this value, inserting a new entry evicts the least recently
+    /// used entries. An entry heavier than this value is evicted as soon as
+    /// it is inserted, and replaces the previous entry of its key.
+    ///
+    /// Defaults to `None` (unbounded).
+    ///
+    /// [`Weigher`]: crate::Weigher
+    pub max_weight: Option<u64>,
     /// Delay between two checks if the expiration queue is empty.
     ///
-    /// Defaults to 100ms.
-    pub empty_delay: Duration,
+    /// The expiration task is woken up when an entry is inserted, so it does
+    /// not need to check an empty queue periodically.
+    ///
+    /// Defaults to `None` (the task waits until an entry is inserted).
+    pub empty_delay: Option<Duration>,
     /// Delay added between each expiration checks.
     ///
     /// This allow to group together expiration of keys with a similar delay.
@@ -26,14 +72,42 @@ pub struct AsyncTtlConfig {
     ///
     /// Defaults to 5ms.
     pub delta_delay: Duration,
+    /// Maximum number of expired entries removed by the expiration task
+    /// before it releases the locks of the cache.
+    ///
+    /// When more entries have expired, the task yields between batches so
+    /// that other tasks can access the cache, until all expired entries have
+    /// been removed. This bounds the time readers wait for the task when many
+    /// entries expire at the same time.
+    ///
+    /// Defaults to `None` (all expired entries are removed at once).
+    pub max_evictions_per_tick: Option<usize>,
+    /// Number of entries of the expiration queue checked on each insertion,
+    /// to remove expired entries without the expiration task.
+    ///
+    /// When set, expired entries are removed lazily: each insertion removes
+    /// up to this number of expired entries from the head of the queue, and
+    /// reading an expired entry removes it. The expiration task does not need
+    /// to be started. At least one entry is checked on each insertion.
+    ///
+    /// Defaults to `None` (expired entries are removed by the expiration
+    /// task).
+    pub lazy_expiration: Option<usize>,
 }
 
 impl AsyncTtlConfig {
     pub fn new(expires_after: Duration) -> Self {
         Self {
             expires_after,
-            empty_delay: DEFAULT_EMPTY_DELAY,
+            policy: ExpirationPolicy::default(),
+            max_age: None,
+            negative_ttl: None,
+            max_capacity: None,
+            max_weight: None,
+            empty_delay: None,
             delta_delay: DEFAULT_DELTA_DELAY,
+            max_evictions_per_tick: None,
+            lazy_expiration: None,
         }
     }
 
@@ -42,22 +116,82 @@ impl AsyncTtlConfig {
     }
 }
 
+/// Expiration policy of an [`AsyncTtl`] cache.
+///
+/// [`AsyncTtl`]: crate::AsyncTtl
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExpirationPolicy {
+    /// Entries expire after a delay since their insertion.
+    #[default]
+    TimeToLive,
+    /// Entries expire after a delay since their last access (sliding
+    /// expiration).
+    ///
+    /// Accessing an entry through the cache methods pushes back its
+    /// expiration.
+    TimeToIdle,
+}
+
 /// Builder for [`AsyncTtlConfig`].
 pub struct AsyncTtlConfigBuilder {
     expires_after: Duration,
+    policy: Option<ExpirationPolicy>,
+    max_age: Option<Duration>,
+    negative_ttl: Option<Duration>,
+    max_capacity: Option<usize>,
+    max_weight: Option<u64>,
     empty_delay: Option<Duration>,
     delta_delay: Option<Duration>,
+    max_evictions_per_tick: Option<usize>,
+    lazy_expiration: Option<usize>,
 }
 
 impl AsyncTtlConfigBuilder {
     fn new(expires_after: Duration) -> Self {
         Self {
             expires_after,
+            policy: None,
+            max_age: None,
+            negative_ttl: None,
+            max_capacity: None,
+            max_weight: None,
             empty_delay: None,
             delta_delay: None,
+            max_evictions_per_tick: None,
+            lazy_expiration: None,
         }
     }
 
+    pub fn policy(mut self, policy: ExpirationPolicy) -> Self {
+        self.policy = Some(policy);
+
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+
+        self
+    }
+
+    pub fn negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.negative_ttl = Some(negative_ttl);
+
+        self
+    }
+
+    pub fn max_capacity(mut self, max_capacity: usize) -> Self {
+        self.max_capacity = Some(max_capacity);
+
+        self
+    }
+
+    pub fn max_weight(mut self, max_weight: u64) -> Self {
+        self.max_weight = Some(max_weight);
+
+        self
+    }
+
     pub fn empty_delay(mut self, empty_delay: Duration) -> Self {
         self.empty_delay = Some(empty_delay);
 
@@ -70,11 +204,30 @@ impl AsyncTtlConfigBuilder {
         self
     }
 
+    pub fn max_evictions_per_tick(mut self, max_evictions_per_tick: usize) -> Self {
+        self.max_evictions_per_tick = Some(max_evictions_per_tick);
+
+        self
+    }
+
+    pub fn lazy_expiration(mut self, lazy_expiration: usize) -> Self {
+        self.lazy_expiration = Some(lazy_expiration);
+
+        self
+    }
+
     pub fn build(self) -> AsyncTtlConfig {
         AsyncTtlConfig {
             expires_after: self.expires_after,
-            empty_delay: self.empty_delay.unwrap_or(DEFAULT_EMPTY_DELAY),
+            policy: self.policy.unwrap_or_default(),
+            max_age: self.max_age,
+            negative_ttl: self.negative_ttl,
+            max_capacity: self.max_capacity,
+            max_weight: self.max_weight,
+            empty_delay: self.empty_delay,
             delta_delay: self.delta_delay.unwrap_or(DEFAULT_DELTA_DELAY),
+            max_evictions_per_tick: self.max_evictions_per_tick,
+            lazy_expiration: self.lazy_expiration,
         }
     }
 }
@@ -0,0 +1,168 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::queue::ExpireKey;
+
+/// Entry stored in the [`CacheMap`] of an [`AsyncTtl`] cache.
+///
+/// This type wraps the cached value with metadata used to track its
+/// expiration.
+///
+/// [`CacheMap`]: crate::CacheMap
+/// [`AsyncTtl`]: crate::AsyncTtl
+#[derive(Debug)]
+pub struct CacheEntry<V> {
+    /// Cached value.
+    value: V,
+    /// Instant at which the entry has been inserted.
+    created_at: Instant,
+    /// Instant at which the entry expires, regardless of accesses.
+    expires_at: Instant,
+    /// Idle timeout of the entry, if it uses sliding expiration.
+    idle_timeout: Option<Duration>,
+    /// Nanoseconds elapsed between the insertion and the last access.
+    last_access: AtomicU64,
+    /// Position of the entry in the expiration queue.
+    expire_key: ExpireKey,
+    /// Tick of the last access to the entry.
+    last_used: AtomicU64,
+    /// Tick of the entry in the access order.
+    access_tick: u64,
+    /// Weight of the entry.
+    weight: u64,
+}
+
+impl<V> CacheEntry<V> {
+    /// Initialize a new [`CacheEntry`].
+    pub(crate) fn new(
+        value: V,
+        created_at: Instant,
+        expires_at: Instant,
+        idle_timeout: Option<Duration>,
+        expire_key: ExpireKey,
+        tick: u64,
+        weight: u64,
+    ) -> Self {
+        Self {
+            value,
+            created_at,
+            expires_at,
+            idle_timeout,
+            last_access: AtomicU64::new(0),
+            expire_key,
+            last_used: AtomicU64::new(tick),
+            access_tick: tick,
+            weight,
+        }
+    }
+
+    /// Returns a reference to the cached value.
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    /// Consumes the entry, returning the cached value.
+    pub fn into_value(self) -> V {
+        self.value
+    }
+
+    /// Returns the instant at which the entry expires.
+    ///
+    /// For entries with sliding expiration, this instant is pushed back each
+    /// time the entry is accessed through the cache.
+    ///
+    /// If the entry has both a maximum age and an idle timeout, the earliest
+    /// of the two expirations is returned.
+    pub fn expires_at(&self) -> Instant {
+        match self.idle_expires_at() {
+            Some(idle_expires_at) => idle_expires_at.min(self.expires_at),
+            None => self.expires_at,
+        }
+    }
+
+    /// Returns when the entry expires if it is not accessed again.
+    ///
+    /// Returns `None` if the entry does not use sliding expiration.
+    fn idle_expires_at(&self) -> Option<Instant> {
+        let idle_timeout = self.idle_timeout?;
+        let last_access = Duration::from_nanos(self.last_access.load(Ordering::Relaxed));
+
+        (self.created_at + last_access).checked_add(idle_timeout)
+    }
+
+    /// Returns whether the expiration of the entry is caused by its idle
+    /// timeout rather than its maximum age.
+    pub(crate) fn expires_idle(&self) -> bool {
+        self.idle_expires_at()
+            .is_some_and(|idle_expires_at| idle_expires_at < self.expires_at)
+    }
+
+    /// Returns the weight of the entry, computed when it was inserted.
+    pub fn weight(&self) -> u64 {
+        self.weight
+    }
+
+    /// Returns whether the entry has expired at the given instant.
+    pub(crate) fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at() <= now
+    }
+
+    /// Records an access to the entry at the given instant and tick.
+    ///
+    /// This pushes back the expiration of entries with sliding expiration.
+    pub(crate) fn touch(&self, now: Instant, tick: u64) {
+        self.last_used.fetch_max(tick, Ordering::Relaxed);
+
+        if self.idle_timeout.is_some() {
+            let elapsed = now.saturating_duration_since(self.created_at);
+            let elapsed = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+
+            self.last_access.fetch_max(elapsed, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the position of the entry in the expiration queue.
+    pub(crate) fn expire_key(&self) -> ExpireKey {
+        self.expire_key
+    }
+
+    /// Sets the position of the entry in the expiration queue.
+    pub(crate) fn set_expire_key(&mut self, expire_key: ExpireKey) {
+        self.expire_key = expire_key;
+    }
+
+    /// Returns the tick of the last access to the entry.
+    pub(crate) fn last_used(&self) -> u64 {
+        self.last_used.load(Ordering::Relaxed)
+    }
+
+    /// Returns the tick of the entry in the access order.
+    pub(crate) fn access_tick(&self) -> u64 {
+        self.access_tick
+    }
+
+    /// Sets the tick of the entry in the access order.
+    pub(crate) fn set_access_tick(&mut self, access_tick: u64) {
+        self.access_tick = access_tick;
+    }
+}
+
+impl<V: Clone> Clone for CacheEntry<V> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            idle_timeout: self.idle_timeout,
+            last_access: AtomicU64::new(self.last_access.load(Ordering::Relaxed)),
+            expire_key: self.expire_key,
+            last_used: AtomicU64::new(self.last_used.load(Ordering::Relaxed)),
+            access_tick: self.access_tick,
+            weight: self.weight,
+        }
+    }
+}
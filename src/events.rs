@@ -0,0 +1,22 @@
+use crate::RemovalCause;
+
+/// Default capacity of the channel used to send [`CacheEvent`].
+pub(crate) const DEFAULT_EVENT_CAPACITY: usize = 1024;
+
+/// Event sent to the subscribers of an [`AsyncTtl`] cache.
+///
+/// Events are received with [`AsyncTtl::subscribe`].
+///
+/// [`AsyncTtl`]: crate::AsyncTtl
+/// [`AsyncTtl::subscribe`]: crate::AsyncTtl::subscribe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheEvent<K> {
+    /// A new entry was inserted.
+    Inserted(K),
+    /// The value of an existing entry was replaced.
+    Replaced(K),
+    /// An entry was removed.
+    ///
+    /// This event is not sent for replaced entries.
+    Removed(K, RemovalCause),
+}
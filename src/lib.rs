@@ -1,79 +1,285 @@
 //! # async-ttl
 //!
-//! Cache with asynchronous locking and key expiration with a fixed time-to-live.
+//! Cache with asynchronous locking and key expiration with a time-to-live.
 //!
 //! This crate provides an [`AsyncTtl`] type that represent a cache where each
-//! entry expires after a fixed amount of type. It support asynchronous with
+//! entry expires after a given amount of time. It support asynchronous with
 //! [tokio] and allow to use custom cache types that implement the [`CacheMap`]
 //! trait.
 //!
-//! Implementations are provided for [`HashMap`] and [`BTreeMap`].
+//! Implementations are provided for [`HashMap`] and [`BTreeMap`]. Values are
+//! stored in the map wrapped in a [`CacheEntry`], which holds the metadata
+//! used to track their expiration.
 //!
 //! ## Usage
 //! When creating a new [`AsyncTtl`] cache, the method returns the created cache
 //! wrapped in an [`Arc`] and a [`AsyncTtlExpireTask`]. This task must be
 //! started for the expired key eviction to work.
 //!
+//! Alternatively, [`AsyncTtl::spawn`] spawns the expiration task on the
+//! current [tokio] runtime and returns a [`Cache`] handle, which dereferences
+//! to the cache. The task is aborted once all the clones of the handle have
+//! been dropped. [`AsyncTtl::spawn_on`] does the same on a given runtime.
+//!
 //! ### Key eviction
 //! The background task automatically removes expired keys from the cache.
 //! The algorithm used is the following:
 //!
-//! - Get the next entry in the expiration queue.
+//! - Get the next entry in the expiration queue, ordered by expiration instant.
 //!   - If an entry is present, wait until its expiration + `delta_delay`
 //!     (defaults to 5ms) and delete all expired keys. This allow to group
-//!     together expiration of keys inserted in a short time window without
+//!     together expiration of keys expiring in a short time window without
 //!     locking the cache in loop.
-//!   - If no entry is present, wait `empty_delay` (defaults to 100ms).
-//! - Do the previous steps indefinitely.
+//!   - If no entry is present, wait until an entry is inserted, or at most
+//!     `empty_delay` if it is set.
+//! - Do the previous steps indefinitely. Inserting an entry that expires before
+//!   the one currently awaited wakes up the task early.
+//!
+//! Inserting or removing an entry does not lock the expiration queue: updates
+//! of the queue are sent through a channel, and applied by the task before it
+//! removes expired keys, or by writers once too many updates are pending.
+//!
+//! When many keys expire at the same time, the `max_evictions_per_tick` option
+//! of the configuration bounds the number of keys removed while the cache is
+//! locked. The task then removes expired keys in batches, and lets other tasks
+//! access the cache between them.
+//!
+//! Removed values are dropped once the locks of the cache have been released.
+//! Values that are expensive to drop can be dropped on the blocking threads of
+//! the runtime with [`AsyncTtlBuilder::drop_in_background`].
+//!
+//! The task only holds a weak reference to the cache, and stops once all the
+//! references to the cache have been dropped. It can also be stopped with the
+//! [`ShutdownHandle`] returned by [`AsyncTtlExpireTask::shutdown_handle`].
+//! Depending on the [`ShutdownMode`], the task stops immediately, removes
+//! expired entries one last time, or removes all remaining entries before
+//! stopping.
+//!
+//! Instead of running the task, expired entries can be removed from another
+//! scheduler with [`AsyncTtlExpireTask::reap_once`], which returns the number
+//! of removed entries and when to call it next. The next deadline is also
+//! returned by [`AsyncTtlExpireTask::next_deadline`].
+//!
+//! ### Lazy expiration
+//! Where spawning a task is not practical, the `lazy_expiration` option of the
+//! configuration removes expired entries without the expiration task. Each
+//! insertion then removes up to the configured number of expired entries from
+//! the head of the expiration queue, and lookups of an expired entry remove
+//! it. The memory used by expired entries stays bounded as long as entries are
+//! inserted, and the returned [`AsyncTtlExpireTask`] can be dropped.
+//!
+//! ### Time-to-live
+//! Entries inserted with [`AsyncTtl::insert`] expire after the `expires_after`
+//! delay of the cache configuration. A custom time-to-live can be set for each
+//! entry with [`AsyncTtl::insert_with_ttl`] and [`AsyncTtl::insert_until`].
+//!
+//! With the [`ExpirationPolicy::TimeToIdle`] policy, entries instead expire
+//! after a delay since their last access through the cache methods. The
+//! expiration task does not need to move entries in the queue when they are
+//! accessed: an entry whose expiration has been pushed back is queued again
+//! when the task reaches its previous expiration.
+//!
+//! The `max_age` option of the configuration bounds the lifetime of entries
+//! regardless of accesses. Combined with a time-to-idle policy, entries expire
+//! at the earliest of the two expirations. [`AsyncTtl::stats`] reports how
+//! many entries expired because of each bound.
+//!
+//! ### Lookups
+//! Entries can be read with [`AsyncTtl::get`], [`AsyncTtl::get_cloned`] and
+//! [`AsyncTtl::contains_key`]. These methods consider expired entries as
+//! absent, even if they have not yet been removed by the expiration task.
+//!
+//! ### Loading entries
+//! [`AsyncTtl::get_or_insert_with`] returns the value of an entry, loading it
+//! with the provided future if it is absent. Concurrent loads of the same key
+//! are coalesced into a single one.
+//!
+//! [`AsyncTtl::try_get_or_insert_with`] does the same with a fallible loader.
+//! Errors can be cached with their own time-to-live using the `negative_ttl`
+//! option of the configuration, to avoid calling a failing backend repeatedly.
+//!
+//! ### Capacity
+//! The number of entries can be bounded with the `max_capacity` option of the
+//! configuration. When the cache is full, inserting an entry evicts the least
+//! recently used entries. Accesses through [`AsyncTtl::get`] and
+//! [`AsyncTtl::get_cloned`] count as uses of an entry.
+//!
+//! The `max_weight` option bounds the total weight of entries instead, which is
+//! computed by the [`Weigher`] set with [`AsyncTtlBuilder::weigher`]. The
+//! current total weight is returned by [`AsyncTtl::weight`]. An entry heavier
+//! than `max_weight` never fits in the cache: it is evicted right after being
+//! inserted, without evicting the other entries.
+//!
+//! ### Invalidation
+//! Entries can be removed before they expire with [`AsyncTtl::remove`],
+//! [`AsyncTtl::remove_many`] and [`AsyncTtl::clear`]. Removed entries are also
+//! removed from the expiration queue: writers apply the pending updates of the
+//! queue once a bounded number of them have accumulated, so stale queue
+//! entries do not pile up until the next expiration.
+//!
+//! ### Eviction listener
+//! A listener can be set with [`AsyncTtlBuilder::eviction_listener`] to be
+//! notified of each entry removed from the cache, with the [`RemovalCause`] of
+//! the removal. The listener is called once the locks of the cache have been
+//! released.
+//!
+//! Listeners performing asynchronous operations can be set with
+//! [`AsyncTtlBuilder::async_eviction_listener`]. The futures it returns are
+//! awaited by the operation that removed the entries, with a bounded
+//! concurrency, after the locks have been released. They run in a spawned
+//! task, so cancelling the operation does not cancel the listener.
+//!
+//! ### Events
+//! Components that need to observe the cache without being set at its
+//! construction can receive a [`CacheEvent`] for each inserted, replaced or
+//! removed entry with [`AsyncTtl::subscribe`].
+//!
+//! ### Sharding
+//! All writes to an [`AsyncTtl`] cache lock the whole cache. Under heavy
+//! concurrent writes, [`ShardedAsyncTtl`] splits the cache into shards that
+//! are locked independently, selected by hashing the keys. It is created with
+//! [`ShardedAsyncTtl::new`] or [`AsyncTtlBuilder::build_sharded`], and a
+//! single [`ShardedExpireTask`] expires the entries of all the shards. Like
+//! [`AsyncTtl::spawn`], [`ShardedAsyncTtl::spawn`] spawns this task and returns
+//! a [`ShardedCache`] handle that aborts it once dropped.
 //!
-//! ### Alternatives
-//! This crate only support using a **fixed** TTL, which reduce the cost of
-//! expired keys eviction and prevents from having expired keys still in the
-//! memory. If you need a variable TTL, consider the [retainer] crate.
+//! ### Clock
+//! Caches read the time from a [`Clock`], the [tokio] clock by default. A
+//! [`ManualClock`] can be set with [`AsyncTtlBuilder::clock`] to test the
+//! expiration of entries without waiting: the clock only moves forward when it
+//! is advanced, and the expiration task wakes up when it reaches the next
+//! expiration.
+//!
+//! The `testing` feature enables the `testing` module, with a test cache
+//! driven by a manual clock whose expired entries are removed on demand, and
+//! assertions on the expiration of entries.
 //!
 //! [`HashMap`]: std::collections::HashMap
 //! [`BTreeMap`]: std::collections::BTreeMap
-//! [retainer]: https://crates.io/crates/retainer
 
+mod builder;
+mod cache;
+mod clock;
 pub mod config;
+mod entry;
+mod events;
+mod listener;
+mod loader;
+mod locked;
+mod lru;
 mod map;
+mod queue;
+mod sharded;
+mod shutdown;
+mod stats;
+mod task;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod weigher;
 
+pub use builder::AsyncTtlBuilder;
+pub use cache::{Cache, CacheHandle, ShardedCache};
+pub use clock::{Clock, ManualClock, TokioClock};
+pub use entry::CacheEntry;
+pub use events::CacheEvent;
+pub use listener::RemovalCause;
 pub use map::CacheMap;
+pub use sharded::{ShardedAsyncTtl, ShardedExpireTask};
+pub use shutdown::{ShutdownHandle, ShutdownMode};
+pub use stats::CacheStats;
+pub use task::{AsyncTtlExpireTask, Reaped};
+pub use weigher::Weigher;
 
-use std::{collections::VecDeque, marker::PhantomData, sync::Arc, time::Duration};
+use std::{
+    fmt,
+    future::Future,
+    hash::Hash,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
 
 use tokio::{
-    sync::{RwLock, RwLockReadGuard},
-    time::{self, Instant},
+    runtime::Handle,
+    sync::{broadcast, Mutex, Notify, RwLock, RwLockReadGuard},
+    time::Instant,
 };
 
-use crate::config::AsyncTtlConfig;
+use crate::{
+    config::{AsyncTtlConfig, ExpirationPolicy},
+    listener::EvictionListener,
+    loader::Loaders,
+    locked::{Garbage, LockedCache, Removed},
+    lru::AccessOrder,
+    queue::{ExpireQueue, ExpireSender},
+    stats::StatsCounter,
+};
 
 /// Async cache with TTL.
 ///
 /// This type provides a cache structure with asynchronous locking and key
-/// expiration with a fixed time-to-live.
+/// expiration with a time-to-live.
 ///
 /// See the [crate] documentation to learn more.
-#[derive(Debug)]
 pub struct AsyncTtl<T, K, V>
 where
-    T: CacheMap<K, V> + Default,
+    T: CacheMap<K, CacheEntry<V>> + Default,
     K: Clone,
 {
-    /// Expiration queue.
-    expires: RwLock<VecDeque<EntryExpire<K>>>,
+    /// Expiration queue, locked by the expiration task and [`clear`].
+    ///
+    /// Writers only try to lock it, to apply pending updates once too many of
+    /// them have accumulated.
+    ///
+    /// [`clear`]: Self::clear
+    expires: Mutex<ExpireQueue<K>>,
+    /// Sender used to update the expiration queue without locking it.
+    expirations: ExpireSender<K>,
     /// Inner cache data.
     data: RwLock<T>,
+    /// Access order of entries, used to evict least recently used entries.
+    ///
+    /// This lock is only acquired while holding the write lock of `data`, so
+    /// it is never contended.
+    access: std::sync::Mutex<AccessOrder<K>>,
+    /// Counter used to order accesses to entries.
+    ticks: AtomicU64,
+    /// Total weight of entries.
+    weight: AtomicU64,
     /// Cache configuration.
     config: AsyncTtlConfig,
+    /// Weigher used to compute the weight of entries.
+    weigher: Option<Arc<dyn Weigher<K, V> + Send + Sync>>,
+    /// Listener called when entries are removed.
+    listener: Option<Arc<EvictionListener<K, V>>>,
+    /// Sender of cache events, initialized on the first subscription.
+    events: Arc<OnceLock<broadcast::Sender<CacheEvent<K>>>>,
+    /// Capacity of the cache events channel.
+    event_capacity: usize,
+    /// Loads in progress.
+    loaders: Loaders<K, V>,
+    /// Cache statistics.
+    stats: Arc<StatsCounter>,
+    /// Source of time of the cache.
+    clock: Arc<dyn Clock>,
+    /// Function dropping removed values in background, if enabled.
+    background_drop: Option<fn(Garbage<T, V>)>,
+    /// Notified when the next expiration of the cache changes or when the
+    /// cache is dropped. Shared with the expiration task.
+    ///
+    /// This field and the weigher, listener, events and statistics are shared
+    /// between the shards of a [`ShardedAsyncTtl`].
+    wakeup: Arc<Notify>,
     /// Required for the `V` generic parameter.
     _value: PhantomData<V>,
 }
 
 impl<T, K, V> AsyncTtl<T, K, V>
 where
-    T: CacheMap<K, V> + Default,
+    T: CacheMap<K, CacheEntry<V>> + Default,
     K: Clone,
 {
     /// Initialize a new [`AsyncTtl`] cache.
@@ -81,131 +287,1115 @@ where
     /// This method returns the cache wrapped in an [`Arc`] and the expiration
     /// task.
     pub fn new(config: AsyncTtlConfig) -> (Arc<Self>, AsyncTtlExpireTask<T, K, V>) {
-        let cache = Arc::new(Self {
-            expires: Default::default(),
-            data: Default::default(),
-            config,
-            _value: PhantomData,
-        });
+        Self::builder(config).build()
+    }
+
+    /// Returns a builder to configure a new [`AsyncTtl`] cache.
+    pub fn builder(config: AsyncTtlConfig) -> AsyncTtlBuilder<T, K, V> {
+        AsyncTtlBuilder::new(config)
+    }
 
-        (cache.clone(), AsyncTtlExpireTask::new(cache))
+    /// Returns the statistics of the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns the total weight of the entries in the cache.
+    ///
+    /// Without [`Weigher`], this is the number of entries.
+    pub fn weight(&self) -> u64 {
+        self.weight.load(Ordering::Relaxed)
+    }
+
+    /// Subscribes to the events of the cache.
+    ///
+    /// The returned receiver gets a [`CacheEvent`] for each entry inserted,
+    /// replaced or removed after the subscription. If a subscriber falls
+    /// behind by more events than the capacity set with
+    /// [`AsyncTtlBuilder::event_capacity`], the oldest events are dropped
+    /// and the receiver returns [`RecvError::Lagged`] with the number of
+    /// missed events.
+    ///
+    /// [`RecvError::Lagged`]: broadcast::error::RecvError::Lagged
+    pub fn subscribe(&self) -> broadcast::Receiver<CacheEvent<K>> {
+        self.events
+            .get_or_init(|| broadcast::channel(self.event_capacity.max(1)).0)
+            .subscribe()
     }
 
     /// Returns a read-only access to the underlying stored data.
+    ///
+    /// The values are stored wrapped in a [`CacheEntry`]. The returned map
+    /// may contain entries that have expired but have not yet been removed
+    /// by the expiration task. Use [`get`] to only access entries that have
+    /// not expired.
+    ///
+    /// [`get`]: Self::get
     pub async fn read(&self) -> RwLockReadGuard<'_, T> {
         self.data.read().await
     }
 
+    /// Returns a read-only access to the value of an entry.
+    ///
+    /// Entries that have expired are considered absent, even if they have not
+    /// yet been removed by the expiration task. With lazy expiration, they are
+    /// removed from the cache. The cache is locked for reading as long as the
+    /// returned guard is alive.
+    ///
+    /// With [`ExpirationPolicy::TimeToIdle`], this pushes back the expiration
+    /// of the entry.
+    pub async fn get(&self, key: &K) -> Option<RwLockReadGuard<'_, V>> {
+        let data = self.data.read().await;
+        let now = self.clock.now();
+
+        let data = match RwLockReadGuard::try_map(data, |data| {
+            let entry = data.get_cache(key).filter(|entry| !entry.is_expired(now))?;
+            entry.touch(now, self.next_tick());
+
+            Some(entry.value())
+        }) {
+            Ok(value) => return Some(value),
+            Err(data) => data,
+        };
+
+        // The entry is either absent or expired.
+        if data.get_cache(key).is_some() {
+            drop(data);
+            self.remove_expired(key).await;
+        }
+
+        None
+    }
+
+    /// Returns a clone of the value of an entry.
+    ///
+    /// Entries that have expired are considered absent, even if they have not
+    /// yet been removed by the expiration task.
+    ///
+    /// With [`ExpirationPolicy::TimeToIdle`], this pushes back the expiration
+    /// of the entry.
+    pub async fn get_cloned(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.get(key).await.map(|value| value.clone())
+    }
+
+    /// Returns whether the cache contains an entry for the given key.
+    ///
+    /// Entries that have expired are considered absent, even if they have not
+    /// yet been removed by the expiration task. With lazy expiration, they are
+    /// removed from the cache. This method does not count as an access to the
+    /// entry.
+    pub async fn contains_key(&self, key: &K) -> bool {
+        let data = self.data.read().await;
+
+        match data.get_cache(key) {
+            Some(entry) if entry.is_expired(self.clock.now()) => {
+                drop(data);
+                self.remove_expired(key).await;
+
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Returns the value of an entry, loading it if it is not present.
+    ///
+    /// If the entry is absent or has expired, the `init` future is awaited
+    /// and its output is inserted into the cache. Concurrent calls for the
+    /// same key are coalesced so that a single load runs, and all callers
+    /// receive its result.
+    ///
+    /// If the task running the load is cancelled, one of the waiting tasks
+    /// runs its own loader instead.
+    pub async fn get_or_insert_with<F, Fut>(&self, key: K, init: F) -> V
+    where
+        K: Hash + Eq,
+        V: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        if let Some(value) = self.get_cloned(&key).await {
+            return value;
+        }
+
+        let load = self.loaders.join(&key);
+        let value = load
+            .cell()
+            .get_or_init(|| async {
+                // The entry may have been inserted by a load that completed
+                // before this one started.
+                if let Some(value) = self.get_cloned(&key).await {
+                    return value;
+                }
+
+                let value = init().await;
+                self.insert(key.clone(), value.clone()).await;
+
+                value
+            })
+            .await;
+
+        value.clone()
+    }
+
+    /// Returns the value of an entry, loading it with a fallible loader if it
+    /// is not present.
+    ///
+    /// This method behaves like [`get_or_insert_with`], but the value is only
+    /// inserted if the loader succeeds. If [`negative_ttl`] is set, errors are
+    /// cached for this delay and returned again instead of calling the
+    /// loader. An error never replaces a value already present in the cache.
+    ///
+    /// If the load fails and errors are not cached, one of the concurrent
+    /// callers for the same key runs its own loader.
+    ///
+    /// [`get_or_insert_with`]: Self::get_or_insert_with
+    /// [`negative_ttl`]: AsyncTtlConfig::negative_ttl
+    pub async fn try_get_or_insert_with<F, Fut, E>(&self, key: K, init: F) -> Result<V, E>
+    where
+        K: Hash + Eq,
+        V: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+        E: Clone + Send + Sync + 'static,
+    {
+        if let Some(value) = self.get_cloned(&key).await {
+            return Ok(value);
+        }
+
+        let load = self.loaders.join(&key);
+        let value = load
+            .cell()
+            .get_or_try_init(|| async {
+                // The entry may have been inserted by a load that completed
+                // before this one started.
+                if let Some(value) = self.get_cloned(&key).await {
+                    return Ok(value);
+                }
+
+                if let Some(error) = self.loaders.cached_error(&key, self.clock.now()) {
+                    return Err(error);
+                }
+
+                match init().await {
+                    Ok(value) => {
+                        self.loaders.clear_error(&key);
+                        self.insert(key.clone(), value.clone()).await;
+
+                        Ok(value)
+                    }
+                    Err(error) => {
+                        if let Some(negative_ttl) = self.config.negative_ttl {
+                            let now = self.clock.now();
+                            let expires_at = deadline(now, negative_ttl);
+
+                            self.loaders
+                                .cache_error(&key, error.clone(), now, expires_at);
+                        }
+
+                        Err(error)
+                    }
+                }
+            })
+            .await?;
+
+        Ok(value.clone())
+    }
+
     /// Inserts a new entry into the cache.
+    ///
+    /// The entry expires after the [`expires_after`] delay of the cache
+    /// configuration. If the key was already present, its value is replaced
+    /// and its time-to-live is reset.
+    ///
+    /// [`expires_after`]: AsyncTtlConfig::expires_after
     pub async fn insert(&self, key: K, value: V) {
-        // Acquire write locks. Locks are acquired in this order to avoid
-        // deadlocks with the expiration tasks.
-        let mut expires = self.expires.write().await;
-        let mut data = self.data.write().await;
-
-        data.insert_cache(key.clone(), value);
-        expires.push_back(EntryExpire::new(
-            key,
-            Instant::now(),
-            self.config.expires_after,
-        ));
+        self.insert_with_ttl(key, value, self.config.expires_after)
+            .await
     }
-}
 
-#[derive(Debug)]
-struct EntryExpire<K> {
-    key: K,
-    created_at: Instant,
-    expires_after: Duration,
-}
+    /// Inserts a new entry into the cache with a custom time-to-live.
+    ///
+    /// The provided delay is used instead of the [`expires_after`] delay of
+    /// the cache configuration, following the configured expiration policy.
+    /// If the key was already present, its value is replaced and its
+    /// time-to-live is reset.
+    ///
+    /// [`expires_after`]: AsyncTtlConfig::expires_after
+    pub async fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        let now = self.clock.now();
+        let expires_at = deadline(now, ttl);
 
-impl<K> EntryExpire<K> {
-    /// Initialize a new [`KeyExpire`].
-    fn new(key: K, created_at: Instant, expires_after: Duration) -> Self {
-        Self {
-            key,
-            created_at,
-            expires_after,
+        match self.config.policy {
+            ExpirationPolicy::TimeToLive => {
+                self.insert_entry(key, value, now, expires_at, None).await
+            }
+            ExpirationPolicy::TimeToIdle => {
+                self.insert_entry(key, value, now, far_future(now), Some(ttl))
+                    .await
+            }
         }
     }
 
-    /// Returns when the entry expires.
+    /// Inserts a new entry into the cache that expires at the given instant.
     ///
-    /// If the entry has already expired, a zero duration is returned.
-    fn expires_in(&self) -> Duration {
-        let elapsed = self.created_at.elapsed();
+    /// The entry expires at this instant regardless of the configured
+    /// expiration policy. If the key was already present, its value is
+    /// replaced and its expiration is updated.
+    pub async fn insert_until(&self, key: K, value: V, expires_at: Instant) {
+        self.insert_entry(key, value, self.clock.now(), expires_at, None)
+            .await
+    }
+
+    /// Inserts a new entry into the cache with the given expiration.
+    async fn insert_entry(
+        &self,
+        key: K,
+        value: V,
+        now: Instant,
+        expires_at: Instant,
+        idle_timeout: Option<Duration>,
+    ) {
+        // Entries never live longer than the configured maximum age.
+        let expires_at = match self.config.max_age {
+            Some(max_age) => expires_at.min(deadline(now, max_age)),
+            None => expires_at,
+        };
+
+        let mut locked = LockedCache::lock(self).await;
+        let is_next = locked.insert(key, value, now, expires_at, idle_timeout);
 
-        // Computes EXPIRES_AFTER - elapsed, returning zero if resulting in
-        // a negative duration (already expired)
-        self.expires_after.saturating_sub(elapsed)
+        // With lazy expiration, inserts remove the expired entries at the
+        // head of the expiration queue. The queue is skipped if it is already
+        // locked, since its owner removes expired entries.
+        if let Some(limit) = self.config.lazy_expiration {
+            if let Ok(mut expires) = self.expires.try_lock() {
+                locked.expire(&mut expires, now, Some(limit));
+            }
+        }
+
+        let removed = locked.unlock();
+
+        // Wake up the expiration task if the entry expires before the one
+        // it is currently waiting for.
+        if is_next {
+            self.wakeup.notify_one();
+        }
+
+        self.notify(removed).await;
+    }
+
+    /// Removes an entry from the cache, returning its value if it was present.
+    pub async fn remove(&self, key: &K) -> Option<V> {
+        let mut locked = LockedCache::lock(self).await;
+        let value = locked.remove(key);
+        self.notify(locked.unlock()).await;
+
+        value
+    }
+
+    /// Removes multiple entries from the cache.
+    ///
+    /// The returned values are in the same order as the provided keys, with
+    /// `None` for keys that were not present.
+    pub async fn remove_many<'a, I>(&self, keys: I) -> Vec<Option<V>>
+    where
+        I: IntoIterator<Item = &'a K>,
+        K: 'a,
+    {
+        let mut locked = LockedCache::lock(self).await;
+        let values = keys.into_iter().map(|key| locked.remove(key)).collect();
+        self.notify(locked.unlock()).await;
+
+        values
+    }
+
+    /// Removes all entries from the cache.
+    pub async fn clear(&self) {
+        let mut expires = self.expires.lock().await;
+        let mut locked = LockedCache::lock(self).await;
+        locked.clear(&mut expires);
+        let removed = locked.unlock_with(expires);
+
+        self.notify(removed).await;
+    }
+
+    /// Applies the pending updates of the expiration queue if too many are
+    /// pending, so that replaced and removed entries do not pile up in the
+    /// queue until the next expiration.
+    ///
+    /// Updates are left pending if the queue is locked, since its owner
+    /// applies them.
+    fn receive_backlog(&self) {
+        if !self.expirations.has_backlog() {
+            return;
+        }
+
+        if let Ok(mut expires) = self.expires.try_lock() {
+            expires.receive();
+        }
+    }
+
+    /// Removes the entry of a key if it has expired and expired entries are
+    /// removed lazily.
+    async fn remove_expired(&self, key: &K) {
+        if self.config.lazy_expiration.is_none() {
+            return;
+        }
+
+        let mut locked = LockedCache::lock(self).await;
+        locked.remove_expired(key, self.clock.now());
+        self.notify(locked.unlock()).await;
+    }
+
+    /// Calls the eviction listener with removed entries.
+    ///
+    /// This must be called after the locks of the cache have been released,
+    /// so the listener can access the cache.
+    async fn notify(&self, removed: Vec<Removed<K, V>>) {
+        if let Some(listener) = &self.listener {
+            listener.notify(removed).await;
+        }
+    }
+
+    /// Returns whether the capacity or the weight of the cache is bounded.
+    fn is_bounded(&self) -> bool {
+        self.config.max_capacity.is_some() || self.config.max_weight.is_some()
+    }
+
+    /// Returns whether the cache exceeds its maximum capacity or weight.
+    fn is_over_capacity(&self, access: &AccessOrder<K>) -> bool {
+        let over_capacity = self
+            .config
+            .max_capacity
+            .is_some_and(|max_capacity| access.len() > max_capacity);
+        let over_weight = self
+            .config
+            .max_weight
+            .is_some_and(|max_weight| self.weight() > max_weight);
+
+        over_capacity || over_weight
+    }
+
+    /// Returns whether an entry of the given weight is heavier than the
+    /// maximum weight of the cache.
+    fn is_over_max_weight(&self, weight: u64) -> bool {
+        self.config
+            .max_weight
+            .is_some_and(|max_weight| weight > max_weight)
+    }
+
+    /// Returns the weight of an entry.
+    fn weigh(&self, key: &K, value: &V) -> u64 {
+        match &self.weigher {
+            Some(weigher) => weigher.weigh(key, value),
+            None => 1,
+        }
+    }
+
+    /// Returns whether the cache has event subscribers.
+    fn has_subscribers(&self) -> bool {
+        self.events
+            .get()
+            .is_some_and(|events| events.receiver_count() > 0)
+    }
+
+    /// Sends an event to the subscribers of the cache.
+    ///
+    /// The event is only created if the cache has subscribers.
+    fn send_event(&self, event: impl FnOnce() -> CacheEvent<K>) {
+        if let Some(events) = self.events.get() {
+            if events.receiver_count() > 0 {
+                // Sending only fails if all receivers have been dropped.
+                let _ = events.send(event());
+            }
+        }
+    }
+
+    /// Returns a new tick used to order accesses to entries.
+    fn next_tick(&self) -> u64 {
+        self.ticks.fetch_add(1, Ordering::Relaxed)
     }
 }
 
-/// [`AsyncTtl`] expiration task.
-///
-/// This type represent the expiration task of a cache and must be started
-/// to ensure expired keys are removed.
-///
-/// See the [crate] documentation to learn more.
-#[derive(Debug, Clone)]
-pub struct AsyncTtlExpireTask<T, K, V>
+impl<T, K, V> AsyncTtl<T, K, V>
 where
-    T: CacheMap<K, V> + Default,
-    K: Clone,
+    T: CacheMap<K, CacheEntry<V>> + Default + Send + Sync + 'static,
+    K: Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
 {
-    cache: Arc<AsyncTtl<T, K, V>>,
+    /// Initialize a new [`AsyncTtl`] cache and spawns its expiration task on
+    /// the current [tokio] runtime.
+    ///
+    /// The expiration task is aborted once all the clones of the returned
+    /// [`Cache`] have been dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a [tokio] runtime.
+    pub fn spawn(config: AsyncTtlConfig) -> Cache<T, K, V> {
+        Self::builder(config).spawn()
+    }
+
+    /// Initialize a new [`AsyncTtl`] cache and spawns its expiration task on
+    /// the runtime of the given [`Handle`].
+    ///
+    /// The expiration task is aborted once all the clones of the returned
+    /// [`Cache`] have been dropped.
+    pub fn spawn_on(config: AsyncTtlConfig, handle: &Handle) -> Cache<T, K, V> {
+        Self::builder(config).spawn_on(handle)
+    }
 }
 
-impl<T, K, V> AsyncTtlExpireTask<T, K, V>
+impl<T, K, V> Drop for AsyncTtl<T, K, V>
 where
-    T: CacheMap<K, V> + Default,
+    T: CacheMap<K, CacheEntry<V>> + Default,
     K: Clone,
 {
-    /// Initialize a new [`AsyncTtlExpireTask`].
-    pub fn new(cache: Arc<AsyncTtl<T, K, V>>) -> Self {
-        Self { cache }
-    }
-
-    /// Start the cache expiration task.
-    ///
-    /// This task will automatically expire cached values based on the provided
-    /// configuration. It contains an infinite loop so you should start it in a
-    /// new [tokio] task.
-    pub async fn run(&self) {
-        loop {
-            // Get next expiration time
-            let duration = {
-                // Explicit scope to ensure the lock is dropped
-                let expires = self.cache.expires.read().await;
-
-                match expires.get(0) {
-                    Some(expire) => expire.expires_in() + self.cache.config.delta_delay,
-                    None => self.cache.config.empty_delay,
-                }
-            };
-
-            time::sleep(duration).await;
-
-            {
-                // Explicit scope to ensure the lock is dropped
-                let mut expires = self.cache.expires.write().await;
-                let mut data = self.cache.data.write().await;
-
-                // Remove all expired entries
-                loop {
-                    if !expires
-                        .get(0)
-                        .map(|entry| entry.expires_in().is_zero())
-                        .unwrap_or(false)
-                    {
-                        break; // Break if the next entry has not expired
-                    }
+    fn drop(&mut self) {
+        // Wake up the expiration task so it stops.
+        self.wakeup.notify_one();
+    }
+}
 
-                    // Remove the entry from the cache
-                    let entry = expires.pop_front().unwrap(); // SAFETY: if the entry does not exist, the loop is stopped in the before statement
-                    data.remove_cache(&entry.key);
-                }
+impl<T, K, V> fmt::Debug for AsyncTtl<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default + fmt::Debug,
+    K: Clone + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncTtl")
+            .field("expires", &self.expires)
+            .field("data", &self.data)
+            .field("weight", &self.weight)
+            .field("config", &self.config)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns the instant after the given delay, saturating to a far future
+/// instant if it overflows.
+fn deadline(now: Instant, delay: Duration) -> Instant {
+    now.checked_add(delay).unwrap_or_else(|| far_future(now))
+}
+
+/// Returns an instant far in the future, used when a time-to-live overflows.
+fn far_future(now: Instant) -> Instant {
+    // Roughly 30 years from now, as done by tokio.
+    now + Duration::from_secs(86400 * 365 * 30)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex, Weak,
+        },
+        thread::{self, ThreadId},
+        time::Duration,
+    };
+
+    use tokio::time;
+
+    use super::*;
+    use crate::queue::MAX_PENDING_RECORDS;
+
+    type TestMap = HashMap<u32, CacheEntry<u32>>;
+    type TestCache = AsyncTtl<TestMap, u32, u32>;
+
+    /// Returns the number of entries in the expiration queue of the cache.
+    async fn queue_len(cache: &TestCache) -> usize {
+        let mut expires = cache.expires.lock().await;
+        expires.receive();
+
+        expires.len()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reinserted_entry_outlives_its_previous_expiration() {
+        let (cache, task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(10)));
+        tokio::spawn(async move { task.run().await });
+
+        cache.insert(1, 1).await;
+        time::sleep(Duration::from_secs(9)).await;
+        cache.insert(1, 2).await;
+
+        // The first insertion would have expired by now.
+        time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(cache.read().await.get(&1).map(CacheEntry::value), Some(&2));
+
+        time::sleep(Duration::from_secs(9)).await;
+        assert!(cache.read().await.get(&1).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn entries_expire_after_their_own_ttl() {
+        let (cache, task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(60)));
+        tokio::spawn(async move { task.run().await });
+
+        cache.insert(1, 1).await;
+        cache.insert_with_ttl(2, 2, Duration::from_secs(10)).await;
+        cache
+            .insert_until(3, 3, Instant::now() + Duration::from_secs(30))
+            .await;
+
+        time::sleep(Duration::from_secs(11)).await;
+        assert!(cache.read().await.get(&2).is_none());
+        assert_eq!(cache.read().await.len(), 2);
+
+        time::sleep(Duration::from_secs(20)).await;
+        assert!(cache.read().await.get(&3).is_none());
+        assert!(cache.read().await.get(&1).is_some());
+
+        time::sleep(Duration::from_secs(30)).await;
+        assert!(cache.read().await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn removed_entries_are_not_expired_again() {
+        let (cache, task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(10)));
+        tokio::spawn(async move { task.run().await });
+
+        for key in 0..4 {
+            cache.insert(key, key).await;
+        }
+        assert_eq!(cache.remove(&0).await, Some(0));
+        assert_eq!(cache.remove(&0).await, None);
+        assert_eq!(
+            cache.remove_many(&[1, 2, 4]).await,
+            vec![Some(1), Some(2), None]
+        );
+        assert_eq!(queue_len(&cache).await, 1);
+
+        // The expiration of the removed entry must not remove the new one.
+        time::sleep(Duration::from_secs(5)).await;
+        cache.insert(0, 10).await;
+        time::sleep(Duration::from_secs(6)).await;
+
+        let data = cache.read().await;
+        assert_eq!(data.get(&0).map(CacheEntry::value), Some(&10));
+        assert!(data.get(&3).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cleared_entries_are_not_expired_again() {
+        let (cache, task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(10)));
+        tokio::spawn(async move { task.run().await });
+
+        for key in 0..4 {
+            cache.insert(key, key).await;
+        }
+        cache.clear().await;
+        assert!(cache.read().await.is_empty());
+        assert_eq!(queue_len(&cache).await, 0);
+
+        time::sleep(Duration::from_secs(5)).await;
+        cache.insert(0, 10).await;
+        time::sleep(Duration::from_secs(6)).await;
+        assert_eq!(cache.read().await.get(&0).map(CacheEntry::value), Some(&10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lookups_ignore_expired_entries_not_yet_removed() {
+        let (cache, _task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(10)));
+
+        cache.insert(1, 1).await;
+        cache.insert_with_ttl(2, 2, Duration::from_secs(20)).await;
+        assert_eq!(cache.get(&1).await.as_deref(), Some(&1));
+
+        // The expiration task is not running, so entries are not removed.
+        time::advance(Duration::from_secs(10)).await;
+        assert!(cache.get(&1).await.is_none());
+        assert!(!cache.contains_key(&1).await);
+        assert_eq!(cache.get_cloned(&2).await, Some(2));
+        assert!(cache.contains_key(&2).await);
+        assert!(cache.read().await.get(&1).is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn accessed_idle_entries_expire_after_their_last_access() {
+        let config = AsyncTtlConfig::builder(Duration::from_secs(10))
+            .policy(ExpirationPolicy::TimeToIdle)
+            .build();
+        let (cache, task) = TestCache::new(config);
+        tokio::spawn(async move { task.run().await });
+
+        cache.insert(1, 1).await;
+        time::sleep(Duration::from_secs(8)).await;
+        assert_eq!(cache.get_cloned(&1).await, Some(1));
+
+        // The task reached the first expiration and queued the entry again.
+        time::sleep(Duration::from_secs(4)).await;
+        assert!(cache.read().await.get(&1).is_some());
+        assert_eq!(queue_len(&cache).await, 1);
+
+        // Checking the presence of the entry does not push back its
+        // expiration.
+        time::sleep(Duration::from_secs(3)).await;
+        assert!(cache.contains_key(&1).await);
+        time::sleep(Duration::from_secs(4)).await;
+        assert!(cache.read().await.get(&1).is_none());
+        assert_eq!(queue_len(&cache).await, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn accessed_idle_entries_expire_at_their_max_age() {
+        let config = AsyncTtlConfig::builder(Duration::from_secs(10))
+            .policy(ExpirationPolicy::TimeToIdle)
+            .max_age(Duration::from_secs(25))
+            .build();
+        let (cache, task) = TestCache::new(config);
+        tokio::spawn(async move { task.run().await });
+
+        cache.insert(1, 1).await;
+        cache.insert(2, 2).await;
+        for _ in 0..4 {
+            time::sleep(Duration::from_secs(5)).await;
+            assert_eq!(cache.get_cloned(&1).await, Some(1));
+        }
+
+        // The entry is still accessed, but reached its maximum age.
+        time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(cache.get_cloned(&1).await, None);
+        time::sleep(Duration::from_secs(1)).await;
+        assert!(cache.read().await.is_empty());
+
+        let stats = cache.stats();
+        assert_eq!(stats.age_expirations, 1);
+        assert_eq!(stats.idle_expirations, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_loads_of_a_key_are_coalesced() {
+        let (cache, _task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(60)));
+        let loads = Arc::new(AtomicU64::new(0));
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                let loads = loads.clone();
+                tokio::spawn(async move {
+                    cache
+                        .get_or_insert_with(1, || async move {
+                            loads.fetch_add(1, Ordering::Relaxed);
+                            time::sleep(Duration::from_millis(10)).await;
+                            42
+                        })
+                        .await
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), 42);
+        }
+        assert_eq!(loads.load(Ordering::Relaxed), 1);
+        // The entry has been queued for expiration once.
+        assert_eq!(queue_len(&cache).await, 1);
+
+        // The completed load is not reused once the entry is removed.
+        cache.remove(&1).await;
+        assert_eq!(cache.get_or_insert_with(1, || async { 43 }).await, 43);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_loads_are_cached_for_negative_ttl() {
+        let config = AsyncTtlConfig::builder(Duration::from_secs(60))
+            .negative_ttl(Duration::from_secs(5))
+            .build();
+        let (cache, _task) = TestCache::new(config);
+        let loads = AtomicU64::new(0);
+        let loader = |result: Result<u32, &'static str>| {
+            let loads = &loads;
+            move || async move {
+                loads.fetch_add(1, Ordering::Relaxed);
+                result
+            }
+        };
+
+        let result = cache.try_get_or_insert_with(1, loader(Err("down"))).await;
+        assert_eq!(result, Err("down"));
+
+        // The cached error is returned without calling the loader.
+        let result = cache.try_get_or_insert_with(1, loader(Ok(1))).await;
+        assert_eq!(result, Err("down"));
+        assert_eq!(loads.load(Ordering::Relaxed), 1);
+
+        // The cached error does not hide a value inserted since.
+        cache.insert(1, 10).await;
+        let result = cache.try_get_or_insert_with(1, loader(Ok(1))).await;
+        assert_eq!(result, Ok(10));
+        cache.remove(&1).await;
+
+        // The loader is called again once the error has expired.
+        time::advance(Duration::from_secs(5)).await;
+        let result = cache.try_get_or_insert_with(1, loader(Ok(1))).await;
+        assert_eq!(result, Ok(1));
+        assert_eq!(loads.load(Ordering::Relaxed), 2);
+        assert_eq!(cache.get_cloned(&1).await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn full_cache_evicts_least_recently_used_entry() {
+        let config = AsyncTtlConfig::builder(Duration::from_secs(60))
+            .max_capacity(2)
+            .build();
+        let (cache, _task) = TestCache::new(config);
+
+        cache.insert(1, 1).await;
+        cache.insert(2, 2).await;
+        cache.get(&1).await;
+        cache.insert(3, 3).await;
+
+        assert!(cache.contains_key(&1).await);
+        assert!(!cache.contains_key(&2).await);
+        assert!(cache.contains_key(&3).await);
+        assert_eq!(cache.stats().capacity_evictions, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn evicted_entry_is_not_expired_again() {
+        let config = AsyncTtlConfig::builder(Duration::from_secs(10))
+            .max_capacity(1)
+            .build();
+        let (cache, task) = TestCache::new(config);
+        tokio::spawn(async move { task.run().await });
+
+        cache.insert(1, 1).await;
+        cache.insert(2, 2).await;
+        time::sleep(Duration::from_secs(5)).await;
+        cache.insert(1, 3).await;
+
+        // The queued expirations of the evicted entries are stale.
+        time::sleep(Duration::from_secs(6)).await;
+        assert_eq!(cache.get_cloned(&1).await, Some(3));
+        assert_eq!(queue_len(&cache).await, 1);
+        assert_eq!(cache.stats().age_expirations, 0);
+    }
+
+    #[tokio::test]
+    async fn heavy_cache_evicts_least_recently_used_entries() {
+        let config = AsyncTtlConfig::builder(Duration::from_secs(60))
+            .max_weight(10)
+            .build();
+        let (cache, _task) = TestCache::builder(config)
+            .weigher(|_: &u32, value: &u32| u64::from(*value))
+            .build();
+
+        cache.insert(1, 4).await;
+        cache.insert(2, 4).await;
+        cache.get(&1).await;
+        cache.insert(3, 5).await;
+
+        assert!(cache.contains_key(&1).await);
+        assert!(!cache.contains_key(&2).await);
+        assert!(cache.contains_key(&3).await);
+        assert_eq!(cache.weight(), 9);
+        assert_eq!(cache.stats().capacity_evictions, 1);
+    }
+
+    #[tokio::test]
+    async fn entry_heavier_than_max_weight_is_evicted_alone() {
+        let config = AsyncTtlConfig::builder(Duration::from_secs(60))
+            .max_weight(10)
+            .build();
+        let (cache, _task) = TestCache::builder(config)
+            .weigher(|_: &u32, value: &u32| u64::from(*value))
+            .build();
+
+        cache.insert(1, 4).await;
+        cache.insert(2, 4).await;
+        cache.insert(3, 50).await;
+
+        assert!(cache.contains_key(&1).await);
+        assert!(cache.contains_key(&2).await);
+        assert!(!cache.contains_key(&3).await);
+        assert_eq!(cache.weight(), 8);
+        assert_eq!(cache.stats().capacity_evictions, 1);
+
+        // The heavy entry still replaces the previous value of its key.
+        cache.insert(1, 50).await;
+        assert!(!cache.contains_key(&1).await);
+        assert_eq!(cache.weight(), 4);
+        assert_eq!(queue_len(&cache).await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn eviction_listener_receives_removal_causes() {
+        let removed = Arc::new(Mutex::new(Vec::new()));
+        let config = AsyncTtlConfig::builder(Duration::from_secs(10))
+            .max_capacity(2)
+            .build();
+        let listener_removed = removed.clone();
+        let (cache, task) = TestCache::builder(config)
+            .eviction_listener(move |key, value, cause| {
+                listener_removed.lock().unwrap().push((key, value, cause));
+            })
+            .build();
+        tokio::spawn(async move { task.run().await });
+
+        cache.insert(1, 1).await;
+        cache.insert(1, 2).await;
+        cache.insert(2, 2).await;
+        cache.insert(3, 3).await;
+        // The value is returned and a clone is sent to the listener.
+        assert_eq!(cache.remove(&2).await, Some(2));
+        time::sleep(Duration::from_secs(11)).await;
+
+        assert_eq!(
+            *removed.lock().unwrap(),
+            [
+                (1, 1, RemovalCause::Replaced),
+                (1, 2, RemovalCause::Capacity),
+                (2, 2, RemovalCause::Explicit),
+                (3, 3, RemovalCause::Expired),
+            ]
+        );
+    }
+
+    /// Async eviction listener counting its concurrent calls.
+    #[derive(Default)]
+    struct CountingListener {
+        running: AtomicU64,
+        max_running: AtomicU64,
+        calls: AtomicU64,
+    }
+
+    impl CountingListener {
+        async fn call(&self) {
+            let running = self.running.fetch_add(1, Ordering::Relaxed) + 1;
+            self.max_running.fetch_max(running, Ordering::Relaxed);
+            time::sleep(Duration::from_millis(10)).await;
+            self.running.fetch_sub(1, Ordering::Relaxed);
+            self.calls.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn counting_cache() -> (Arc<TestCache>, Arc<CountingListener>) {
+        let counter = Arc::new(CountingListener::default());
+        let listener = counter.clone();
+        let (cache, _task) = TestCache::builder(AsyncTtlConfig::new(Duration::from_secs(60)))
+            .async_eviction_listener(move |_, _, _| {
+                let listener = listener.clone();
+                async move { listener.call().await }
+            })
+            .listener_concurrency(2)
+            .build();
+
+        (cache, counter)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn async_eviction_listener_runs_with_bounded_concurrency() {
+        let (cache, counter) = counting_cache();
+
+        for key in 0..6 {
+            cache.insert(key, key).await;
+        }
+        cache.remove_many(&[0, 1, 2, 3, 4, 5]).await;
+
+        // The listener has been awaited by the removal.
+        assert_eq!(counter.calls.load(Ordering::Relaxed), 6);
+        assert_eq!(counter.max_running.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn async_eviction_listener_completes_if_removal_is_cancelled() {
+        let (cache, counter) = counting_cache();
+
+        for key in 0..6 {
+            cache.insert(key, key).await;
+        }
+        let removal = time::timeout(
+            Duration::from_millis(5),
+            cache.remove_many(&[0, 1, 2, 3, 4, 5]),
+        );
+        assert!(removal.await.is_err());
+        assert!(cache.read().await.is_empty());
+
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(counter.calls.load(Ordering::Relaxed), 6);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn subscribers_receive_events_in_order() {
+        let (cache, task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(10)));
+        tokio::spawn(async move { task.run().await });
+        let mut events = cache.subscribe();
+
+        cache.insert(1, 1).await;
+        cache.insert(1, 2).await;
+        cache.insert(2, 2).await;
+        cache.remove(&1).await;
+        time::sleep(Duration::from_secs(11)).await;
+
+        let expected = [
+            CacheEvent::Inserted(1),
+            CacheEvent::Replaced(1),
+            CacheEvent::Inserted(2),
+            CacheEvent::Removed(1, RemovalCause::Explicit),
+            CacheEvent::Removed(2, RemovalCause::Expired),
+        ];
+        for event in expected {
+            assert_eq!(events.recv().await.unwrap(), event);
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn lagging_subscriber_misses_oldest_events() {
+        let (cache, _task) = TestCache::builder(AsyncTtlConfig::new(Duration::from_secs(60)))
+            .event_capacity(2)
+            .build();
+        let mut events = cache.subscribe();
+
+        for key in 0..5 {
+            cache.insert(key, key).await;
+        }
+
+        assert_eq!(
+            events.recv().await,
+            Err(broadcast::error::RecvError::Lagged(3))
+        );
+        assert_eq!(events.recv().await.unwrap(), CacheEvent::Inserted(3));
+        assert_eq!(events.recv().await.unwrap(), CacheEvent::Inserted(4));
+    }
+
+    #[tokio::test]
+    async fn replaced_entries_do_not_pile_up_in_expiration_queue() {
+        let (cache, _task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(3600)));
+
+        for i in 0..10 * MAX_PENDING_RECORDS as u32 {
+            cache.insert(i % 10, i).await;
+        }
+
+        assert!(cache.expirations.pending() <= MAX_PENDING_RECORDS);
+        assert_eq!(queue_len(&cache).await, 10);
+    }
+
+    type ProbeCache = AsyncTtl<HashMap<u32, CacheEntry<DropProbe>>, u32, DropProbe>;
+
+    /// Value recording the thread that drops it, and whether the locks of its
+    /// cache were released at that time.
+    struct DropProbe {
+        cache: Weak<ProbeCache>,
+        drops: Arc<Mutex<Vec<(ThreadId, bool)>>>,
+    }
+
+    impl Drop for DropProbe {
+        fn drop(&mut self) {
+            let unlocked = self.cache.upgrade().is_none_or(|cache| {
+                cache.data.try_write().is_ok() && cache.expires.try_lock().is_ok()
+            });
+
+            let drop = (thread::current().id(), unlocked);
+            self.drops.lock().unwrap().push(drop);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn removed_values_are_dropped_after_locks_are_released() {
+        let (cache, task) = ProbeCache::new(AsyncTtlConfig::new(Duration::from_secs(1)));
+        tokio::spawn(async move { task.run().await });
+        let drops = Arc::new(Mutex::new(Vec::new()));
+        let probe = || DropProbe {
+            cache: Arc::downgrade(&cache),
+            drops: drops.clone(),
+        };
+
+        // Replaced, expired and cleared values.
+        cache.insert(1, probe()).await;
+        cache.insert(1, probe()).await;
+        time::sleep(Duration::from_secs(2)).await;
+        cache.insert(2, probe()).await;
+        cache.insert(3, probe()).await;
+        cache.clear().await;
+
+        let drops = drops.lock().unwrap();
+        assert_eq!(drops.len(), 4);
+        assert!(drops
+            .iter()
+            .all(|&(thread, unlocked)| { thread == thread::current().id() && unlocked }));
+    }
+
+    #[tokio::test]
+    async fn drop_in_background_drops_values_on_blocking_threads() {
+        let config = AsyncTtlConfig::new(Duration::from_secs(60));
+        let (cache, _task) = ProbeCache::builder(config).drop_in_background().build();
+        let drops = Arc::new(Mutex::new(Vec::new()));
+        let probe = || DropProbe {
+            cache: Arc::downgrade(&cache),
+            drops: drops.clone(),
+        };
+
+        cache.insert(1, probe()).await;
+        cache.insert(1, probe()).await;
+        while drops.lock().unwrap().is_empty() {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+
+        let drops = drops.lock().unwrap();
+        assert_eq!(drops.len(), 1);
+        assert_ne!(drops[0].0, thread::current().id());
+        assert!(drops[0].1);
+    }
+
+    #[tokio::test]
+    async fn manual_clock_drives_expiration_task() {
+        let clock = ManualClock::new();
+        let config = AsyncTtlConfig::new(Duration::from_secs(10));
+        let (cache, task) = TestCache::builder(config).clock(clock.clone()).build();
+        tokio::spawn(async move { task.run().await });
+
+        cache.insert(1, 1).await;
+        clock.advance(Duration::from_secs(9));
+        tokio::task::yield_now().await;
+        assert!(cache.contains_key(&1).await);
+
+        clock.advance(Duration::from_secs(2));
+        time::timeout(Duration::from_secs(5), async {
+            while !cache.read().await.is_empty() {
+                tokio::task::yield_now().await;
             }
+        })
+        .await
+        .expect("entry was not removed once the clock was advanced");
+    }
+
+    #[tokio::test]
+    async fn lazy_expiration_removes_expired_entries_without_task() {
+        let clock = ManualClock::new();
+        let config = AsyncTtlConfig::builder(Duration::from_secs(1))
+            .lazy_expiration(2)
+            .build();
+        let (cache, task) = TestCache::builder(config).clock(clock.clone()).build();
+        drop(task);
+
+        for key in 0..10 {
+            cache.insert(key, key).await;
+        }
+        clock.advance(Duration::from_secs(2));
+
+        // Each insertion removes up to two expired entries.
+        cache.insert(100, 100).await;
+        assert_eq!(cache.weight(), 9);
+
+        // Reading an expired entry removes it.
+        assert_eq!(cache.get_cloned(&9).await, None);
+        assert!(!cache.contains_key(&8).await);
+        assert_eq!(cache.weight(), 7);
+
+        for key in 101..105 {
+            cache.insert(key, key).await;
         }
+        assert_eq!(cache.weight(), 5);
+        assert_eq!(cache.stats().age_expirations, 10);
     }
 }
@@ -0,0 +1,151 @@
+use std::{
+    fmt,
+    future::{poll_fn, Future},
+    panic,
+    pin::Pin,
+    task::Poll,
+};
+
+use tokio::runtime::Handle;
+
+use crate::locked::Removed;
+
+/// Default maximum number of concurrent calls to an async eviction listener.
+pub(crate) const DEFAULT_LISTENER_CONCURRENCY: usize = 16;
+
+/// Future returned by an async eviction listener.
+type ListenerFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Cause of the removal of an entry from an [`AsyncTtl`] cache.
+///
+/// [`AsyncTtl`]: crate::AsyncTtl
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RemovalCause {
+    /// The entry expired.
+    Expired,
+    /// The entry was replaced by a new value for the same key.
+    Replaced,
+    /// The entry was explicitly removed.
+    Explicit,
+    /// The entry was evicted because the cache reached its maximum capacity
+    /// or weight.
+    Capacity,
+}
+
+/// Eviction listener of an [`AsyncTtl`] cache.
+///
+/// [`AsyncTtl`]: crate::AsyncTtl
+pub(crate) struct EvictionListener<K, V> {
+    /// Function called for each removed entry.
+    listener: Listener<K, V>,
+    /// Function used to clone values that are also returned to the caller,
+    /// such as with explicit removals.
+    clone_value: fn(&V) -> V,
+    /// Maximum number of concurrent calls to an async listener.
+    concurrency: usize,
+}
+
+enum Listener<K, V> {
+    Sync(Box<dyn Fn(K, V, RemovalCause) + Send + Sync>),
+    Async(Box<dyn Fn(K, V, RemovalCause) -> ListenerFuture + Send + Sync>),
+}
+
+impl<K, V> EvictionListener<K, V> {
+    pub(crate) fn new<F>(listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+        V: Clone,
+    {
+        Self {
+            listener: Listener::Sync(Box::new(listener)),
+            clone_value: V::clone,
+            concurrency: DEFAULT_LISTENER_CONCURRENCY,
+        }
+    }
+
+    pub(crate) fn new_async<F, Fut>(listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+        V: Clone,
+    {
+        Self {
+            listener: Listener::Async(Box::new(move |key, value, cause| {
+                Box::pin(listener(key, value, cause))
+            })),
+            clone_value: V::clone,
+            concurrency: DEFAULT_LISTENER_CONCURRENCY,
+        }
+    }
+
+    /// Sets the maximum number of concurrent calls to an async listener.
+    pub(crate) fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency;
+    }
+
+    /// Calls the listener with removed entries.
+    pub(crate) async fn notify(&self, removed: Vec<Removed<K, V>>) {
+        match &self.listener {
+            Listener::Sync(listener) => {
+                for (key, value, cause) in removed {
+                    listener(key, value, cause);
+                }
+            }
+            Listener::Async(listener) => {
+                let futures: Vec<_> = removed
+                    .into_iter()
+                    .map(|(key, value, cause)| listener(key, value, cause))
+                    .collect();
+                let batch = join_bounded(futures.into_iter(), self.concurrency);
+
+                // The batch runs in its own task so that the listener is still
+                // called for all entries if the caller is cancelled.
+                let Ok(handle) = Handle::try_current() else {
+                    return batch.await;
+                };
+                if let Err(error) = handle.spawn(batch).await {
+                    if error.is_panic() {
+                        panic::resume_unwind(error.into_panic());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns a clone of a value.
+    pub(crate) fn clone_value(&self, value: &V) -> V {
+        (self.clone_value)(value)
+    }
+}
+
+impl<K, V> fmt::Debug for EvictionListener<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EvictionListener").finish_non_exhaustive()
+    }
+}
+
+/// Awaits all futures, running at most `limit` futures concurrently.
+async fn join_bounded<I>(mut futures: I, limit: usize)
+where
+    I: Iterator<Item = ListenerFuture>,
+{
+    let limit = limit.max(1);
+    let mut running = Vec::with_capacity(limit);
+
+    poll_fn(|cx| loop {
+        running.extend(futures.by_ref().take(limit - running.len()));
+
+        if running.is_empty() {
+            return Poll::Ready(());
+        }
+
+        // Poll running futures, and start new ones if some completed.
+        let len = running.len();
+        running.retain_mut(|future| future.as_mut().poll(cx).is_pending());
+
+        if running.len() == len {
+            return Poll::Pending;
+        }
+    })
+    .await
+}
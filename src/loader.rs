@@ -0,0 +1,156 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use tokio::{sync::OnceCell, time::Instant};
+
+/// Loads in progress in an [`AsyncTtl`] cache.
+///
+/// Concurrent loads of the same key share the same [`OnceCell`], so only one
+/// of them runs its loader while the others wait for its result. Errors
+/// returned by fallible loaders are also stored here when negative caching is
+/// enabled.
+///
+/// [`AsyncTtl`]: crate::AsyncTtl
+#[derive(Debug)]
+pub(crate) struct Loaders<K, V> {
+    loading: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+    errors: Mutex<HashMap<K, CachedError>>,
+}
+
+/// Error returned by a loader, cached until it expires.
+#[derive(Debug)]
+struct CachedError {
+    /// Type-erased error, since the error type is chosen on each load.
+    error: Box<dyn Any + Send + Sync>,
+    expires_at: Instant,
+}
+
+impl<K, V> Loaders<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Joins the load of a key, starting a new one if none is in progress.
+    pub(crate) fn join(&self, key: &K) -> LoadGuard<'_, K, V> {
+        let cell = self
+            .lock()
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(OnceCell::new()))
+            .clone();
+
+        LoadGuard {
+            loaders: self,
+            key: key.clone(),
+            cell: Some(cell),
+        }
+    }
+
+    /// Returns the cached error of a key, if it has not expired.
+    ///
+    /// Errors of another type than `E` are ignored.
+    pub(crate) fn cached_error<E>(&self, key: &K, now: Instant) -> Option<E>
+    where
+        E: Clone + 'static,
+    {
+        let mut errors = lock(&self.errors);
+        let cached = errors.get(key)?;
+
+        if cached.expires_at <= now {
+            errors.remove(key);
+            return None;
+        }
+
+        cached.error.downcast_ref::<E>().cloned()
+    }
+
+    /// Caches the error of a key until the given instant.
+    pub(crate) fn cache_error<E>(&self, key: &K, error: E, now: Instant, expires_at: Instant)
+    where
+        E: Send + Sync + 'static,
+    {
+        let mut errors = lock(&self.errors);
+
+        // Errors are only removed lazily, so remove expired errors before
+        // inserting a new one to bound the memory usage.
+        errors.retain(|_, cached| cached.expires_at > now);
+        errors.insert(
+            key.clone(),
+            CachedError {
+                error: Box::new(error),
+                expires_at,
+            },
+        );
+    }
+
+    /// Removes the cached error of a key.
+    pub(crate) fn clear_error(&self, key: &K) {
+        lock(&self.errors).remove(key);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<K, Arc<OnceCell<V>>>> {
+        lock(&self.loading)
+    }
+}
+
+impl<K, V> Default for Loaders<K, V> {
+    fn default() -> Self {
+        Self {
+            loading: Mutex::new(HashMap::new()),
+            errors: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // The locks are never held across panicking code.
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Guard returned by [`Loaders::join`].
+///
+/// The load is removed from the in-progress loads when the last guard is
+/// dropped, whether the load completed or was cancelled.
+pub(crate) struct LoadGuard<'a, K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    loaders: &'a Loaders<K, V>,
+    key: K,
+    /// Shared cell of the load, only taken when the guard is dropped.
+    cell: Option<Arc<OnceCell<V>>>,
+}
+
+impl<K, V> LoadGuard<'_, K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Returns the cell holding the result of the load.
+    pub(crate) fn cell(&self) -> &OnceCell<V> {
+        self.cell.as_ref().expect("cell is only taken on drop")
+    }
+}
+
+impl<K, V> Drop for LoadGuard<'_, K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    fn drop(&mut self) {
+        let mut loading = self.loaders.lock();
+
+        // References to the cell are only created and released while holding
+        // the lock, so once this guard has released its reference, the cell
+        // is only referenced by the map if no other task is waiting for the
+        // load.
+        drop(self.cell.take());
+        let is_last = loading
+            .get(&self.key)
+            .is_some_and(|cell| Arc::strong_count(cell) == 1);
+
+        if is_last {
+            loading.remove(&self.key);
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    mem,
+    sync::{atomic::Ordering, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use tokio::{
+    sync::{MutexGuard as QueueGuard, RwLockWriteGuard},
+    time::Instant,
+};
+
+use crate::{
+    lru::AccessOrder, queue::ExpireQueue, AsyncTtl, CacheEntry, CacheEvent, CacheMap, RemovalCause,
+};
+
+/// Entry removed from the cache, to be sent to the eviction listener.
+pub(crate) type Removed<K, V> = (K, V, RemovalCause);
+
+/// Values removed from the cache that are not sent to the eviction listener.
+///
+/// They are dropped once the cache has been unlocked, so that deallocating
+/// large values does not block other tasks.
+pub(crate) struct Garbage<T, V> {
+    /// Removed entries.
+    entries: Vec<CacheEntry<V>>,
+    /// Data map replaced when the cache was cleared.
+    map: Option<T>,
+}
+
+impl<T, V> Garbage<T, V> {
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.map.is_none()
+    }
+}
+
+/// Write access to an [`AsyncTtl`] cache.
+///
+/// This type holds the write lock of the cache data and keeps its data map,
+/// access order and expiration queue consistent. The expiration queue is
+/// updated through its [`ExpireSender`], and operations that read it take it
+/// as an argument, locked before the cache. Removed entries are collected so
+/// that the eviction listener can be called once the lock is released with
+/// [`unlock`], and other removed values are dropped after the lock is
+/// released.
+///
+/// [`ExpireSender`]: crate::queue::ExpireSender
+/// [`unlock`]: Self::unlock
+pub(crate) struct LockedCache<'a, T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    cache: &'a AsyncTtl<T, K, V>,
+    data: RwLockWriteGuard<'a, T>,
+    removed: Vec<Removed<K, V>>,
+    garbage: Garbage<T, V>,
+}
+
+impl<'a, T, K, V> LockedCache<'a, T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    /// Acquires the write lock of the cache.
+    pub(crate) async fn lock(cache: &'a AsyncTtl<T, K, V>) -> Self {
+        let data = cache.data.write().await;
+
+        Self {
+            cache,
+            data,
+            removed: Vec::new(),
+            garbage: Garbage {
+                entries: Vec::new(),
+                map: None,
+            },
+        }
+    }
+
+    /// Releases the lock, returning the removed entries.
+    ///
+    /// Pending updates of the expiration queue are applied once the lock is
+    /// released if too many of them have accumulated. Removed values that are
+    /// not sent to the eviction listener are dropped once the lock is
+    /// released, or sent to the blocking threads of the runtime if the cache
+    /// drops values in background.
+    pub(crate) fn unlock(self) -> Vec<Removed<K, V>> {
+        self.release(None)
+    }
+
+    /// Releases the lock and the given lock of the expiration queue,
+    /// returning the removed entries.
+    ///
+    /// Removed values are dropped once both locks are released, like with
+    /// [`unlock`].
+    ///
+    /// [`unlock`]: Self::unlock
+    pub(crate) fn unlock_with(self, expires: QueueGuard<'_, ExpireQueue<K>>) -> Vec<Removed<K, V>> {
+        self.release(Some(expires))
+    }
+
+    /// Releases the lock and the lock of the expiration queue if it is held,
+    /// then drops the removed values.
+    fn release(self, expires: Option<QueueGuard<'_, ExpireQueue<K>>>) -> Vec<Removed<K, V>> {
+        let Self {
+            cache,
+            data,
+            removed,
+            garbage,
+        } = self;
+        drop(data);
+        drop(expires);
+        cache.receive_backlog();
+
+        if !garbage.is_empty() {
+            match cache.background_drop {
+                Some(drop_in_background) => drop_in_background(garbage),
+                None => drop(garbage),
+            }
+        }
+
+        removed
+    }
+
+    /// Inserts a new entry with the given expiration.
+    ///
+    /// Returns whether the entry is the next one to expire.
+    pub(crate) fn insert(
+        &mut self,
+        key: K,
+        value: V,
+        now: Instant,
+        expires_at: Instant,
+        idle_timeout: Option<Duration>,
+    ) -> bool {
+        // Entries with sliding expiration initially expire after their idle
+        // timeout.
+        let next_expiration = idle_timeout
+            .and_then(|idle_timeout| now.checked_add(idle_timeout))
+            .map_or(expires_at, |idle_expires_at| {
+                idle_expires_at.min(expires_at)
+            });
+
+        let is_next = self
+            .cache
+            .expirations
+            .next_expiration()
+            .is_none_or(|next| next_expiration < next);
+
+        let expire_key = self.cache.expirations.push(key.clone(), next_expiration);
+        let tick = self.cache.next_tick();
+        let weight = self.cache.weigh(&key, &value);
+        let oversized_key = self.cache.is_over_max_weight(weight).then(|| key.clone());
+        let entry = CacheEntry::new(
+            value,
+            now,
+            expires_at,
+            idle_timeout,
+            expire_key,
+            tick,
+            weight,
+        );
+
+        // The access order is only tracked if the capacity is bounded.
+        if self.cache.is_bounded() {
+            self.access().push(tick, key.clone());
+        }
+
+        self.cache.weight.fetch_add(weight, Ordering::Relaxed);
+
+        // Remove the expiration of the replaced entry so it does not evict
+        // the new value.
+        let event_key = self.needs_keys().then(|| key.clone());
+        match (self.data.insert_cache(key, entry), event_key) {
+            (Some(previous), Some(key)) => {
+                self.detach(&previous);
+                self.cache.send_event(|| CacheEvent::Replaced(key.clone()));
+                self.collect(key, previous, RemovalCause::Replaced);
+            }
+            (Some(previous), None) => {
+                self.detach(&previous);
+                self.garbage.entries.push(previous);
+            }
+            (None, Some(key)) => self.cache.send_event(|| CacheEvent::Inserted(key)),
+            (None, None) => {}
+        }
+
+        // An entry heavier than the maximum weight cannot fit in the cache,
+        // so it is evicted instead of the other entries.
+        if let Some(key) = oversized_key {
+            if let Some(entry) = self.data.remove_cache(&key) {
+                self.evict(key, entry);
+            }
+
+            return false;
+        }
+
+        self.evict_lru();
+
+        is_next
+    }
+
+    /// Removes an entry, returning its value if it was present.
+    pub(crate) fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.data.remove_cache(key)?;
+        self.detach(&entry);
+
+        let value = entry.into_value();
+        self.cache
+            .send_event(|| CacheEvent::Removed(key.clone(), RemovalCause::Explicit));
+
+        if let Some(listener) = &self.cache.listener {
+            let removed = listener.clone_value(&value);
+            self.removed
+                .push((key.clone(), removed, RemovalCause::Explicit));
+        }
+
+        Some(value)
+    }
+
+    /// Removes all entries.
+    pub(crate) fn clear(&mut self, expires: &mut ExpireQueue<K>) {
+        // Entries are removed one by one from the expiration queue, which
+        // contains all keys, to notify the eviction listener and subscribers.
+        if self.needs_keys() {
+            for key in expires.drain() {
+                if let Some(entry) = self.data.remove_cache(&key) {
+                    self.removed(key, entry, RemovalCause::Explicit);
+                }
+            }
+        }
+
+        expires.clear();
+        self.cache.expirations.sync(expires);
+        self.garbage.map = Some(mem::take(&mut *self.data));
+        self.access().clear();
+        self.cache.weight.store(0, Ordering::Relaxed);
+    }
+
+    /// Removes the entries that have expired at the given instant, checking
+    /// at most `limit` entries of the expiration queue.
+    ///
+    /// Returns the number of removed entries.
+    pub(crate) fn expire(
+        &mut self,
+        expires: &mut ExpireQueue<K>,
+        now: Instant,
+        limit: Option<usize>,
+    ) -> usize {
+        // Writers cannot update the queue while the cache is locked, so it
+        // is consistent with the data map once the updates are received.
+        expires.receive();
+
+        let limit = limit.map(|limit| limit.max(1));
+        let mut count = 0;
+        let mut removed = 0;
+
+        while limit.is_none_or(|limit| count < limit) {
+            let Some((expire_key, key)) = expires.pop_expired(now) else {
+                break;
+            };
+            count += 1;
+
+            let Some(entry) = self
+                .data
+                .get_cache_mut(&key)
+                .filter(|entry| entry.expire_key() == expire_key)
+            else {
+                continue;
+            };
+
+            // Entries with sliding expiration may have been accessed since
+            // they were queued, in which case they are queued again with
+            // their new expiration.
+            let expires_at = entry.expires_at();
+            if expires_at > now {
+                entry.set_expire_key(self.cache.expirations.push(key, expires_at));
+                continue;
+            }
+
+            self.cache.stats.record_expiration(entry.expires_idle());
+
+            // The entry has already been removed from the expiration queue.
+            if let Some(entry) = self.data.remove_cache(&key) {
+                self.untrack(&entry);
+                self.removed(key, entry, RemovalCause::Expired);
+                removed += 1;
+            }
+        }
+
+        // Receive entries queued again.
+        expires.receive();
+        self.cache.expirations.sync(expires);
+
+        removed
+    }
+
+    /// Removes the entry of a key if it has expired at the given instant.
+    pub(crate) fn remove_expired(&mut self, key: &K, now: Instant) {
+        let Some(entry) = self
+            .data
+            .get_cache(key)
+            .filter(|entry| entry.is_expired(now))
+        else {
+            return;
+        };
+        self.cache.stats.record_expiration(entry.expires_idle());
+
+        if let Some(entry) = self.data.remove_cache(key) {
+            self.detach(&entry);
+            self.removed(key.clone(), entry, RemovalCause::Expired);
+        }
+    }
+
+    /// Evicts the least recently used entries until the cache fits in its
+    /// maximum capacity and weight.
+    fn evict_lru(&mut self) {
+        while self.cache.is_over_capacity(&self.access()) {
+            let Some((tick, key)) = self.access().pop() else {
+                break;
+            };
+            let Some(entry) = self.data.get_cache_mut(&key) else {
+                continue;
+            };
+
+            // The entry may have been accessed since it was queued, in which
+            // case it is queued again with its last access.
+            let last_used = entry.last_used();
+            if last_used > tick {
+                entry.set_access_tick(last_used);
+                self.access().push(last_used, key);
+                continue;
+            }
+
+            if let Some(entry) = self.data.remove_cache(&key) {
+                self.evict(key, entry);
+            }
+        }
+    }
+
+    /// Evicts an entry that has been removed from the data map because of
+    /// the capacity of the cache.
+    fn evict(&mut self, key: K, entry: CacheEntry<V>) {
+        self.detach(&entry);
+        self.cache.stats.record_capacity_eviction();
+        self.removed(key, entry, RemovalCause::Capacity);
+    }
+
+    /// Removes an entry that has been removed from the data map from the
+    /// expiration queue and the access order.
+    fn detach(&mut self, entry: &CacheEntry<V>) {
+        self.cache.expirations.remove(entry.expire_key());
+        self.untrack(entry);
+    }
+
+    /// Removes an entry that has been removed from the data map from the
+    /// access order and the total weight.
+    fn untrack(&mut self, entry: &CacheEntry<V>) {
+        self.access().remove(entry.access_tick());
+        self.cache
+            .weight
+            .fetch_sub(entry.weight(), Ordering::Relaxed);
+    }
+
+    /// Sends the removal event of an entry and collects it.
+    fn removed(&mut self, key: K, entry: CacheEntry<V>, cause: RemovalCause) {
+        self.cache
+            .send_event(|| CacheEvent::Removed(key.clone(), cause));
+        self.collect(key, entry, cause);
+    }
+
+    /// Collects a removed entry, to be sent to the eviction listener if the
+    /// cache has one, or dropped once the cache is unlocked.
+    fn collect(&mut self, key: K, entry: CacheEntry<V>, cause: RemovalCause) {
+        if self.cache.listener.is_some() {
+            self.removed.push((key, entry.into_value(), cause));
+        } else {
+            self.garbage.entries.push(entry);
+        }
+    }
+
+    /// Locks the access order of the cache.
+    ///
+    /// The access order is only locked while holding the write lock of the
+    /// data map, so this never blocks.
+    fn access(&self) -> MutexGuard<'a, AccessOrder<K>> {
+        self.cache
+            .access
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns whether the keys of inserted and removed entries are needed to
+    /// notify the eviction listener or subscribers.
+    fn needs_keys(&self) -> bool {
+        self.cache.listener.is_some() || self.cache.has_subscribers()
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+
+/// Access order of the entries of an [`AsyncTtl`] cache.
+///
+/// Entries are indexed by the tick of their last access known when they were
+/// queued. Accesses through read locks only update the tick stored in the
+/// entry, so an entry may be queued with an older tick: it is queued again
+/// with its current tick when it reaches the front of the queue.
+///
+/// [`AsyncTtl`]: crate::AsyncTtl
+#[derive(Debug)]
+pub(crate) struct AccessOrder<K> {
+    /// Queued keys, indexed by access tick.
+    entries: BTreeMap<u64, K>,
+}
+
+impl<K> AccessOrder<K> {
+    /// Push an entry in the queue.
+    pub(crate) fn push(&mut self, tick: u64, key: K) {
+        self.entries.insert(tick, key);
+    }
+
+    /// Remove an entry from the queue.
+    pub(crate) fn remove(&mut self, tick: u64) {
+        self.entries.remove(&tick);
+    }
+
+    /// Remove all entries from the queue.
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Remove and returns the least recently used entry.
+    pub(crate) fn pop(&mut self) -> Option<(u64, K)> {
+        self.entries.pop_first()
+    }
+
+    /// Returns the number of queued entries.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl<K> Default for AccessOrder<K> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+}
@@ -12,25 +12,47 @@ use std::{
 /// Methods are suffixed with `_cache` to differentiate them from default
 /// type methods.
 ///
+/// The map of an [`AsyncTtl`] cache stores each value wrapped in a
+/// [`CacheEntry`], so a cache of `V` values requires a
+/// `CacheMap<K, CacheEntry<V>>`. Before version 0.2, the map stored the
+/// values directly.
+///
 /// [`AsyncTtl`]: crate::AsyncTtl
+/// [`CacheEntry`]: crate::CacheEntry
 pub trait CacheMap<K, V> {
     /// Insert a new entry in the map.
-    fn insert_cache(&mut self, key: K, value: V);
+    ///
+    /// If the map already contained this key, the previous value is returned.
+    fn insert_cache(&mut self, key: K, value: V) -> Option<V>;
+
+    /// Returns a reference to the value of an entry.
+    fn get_cache(&self, key: &K) -> Option<&V>;
+
+    /// Returns a mutable reference to the value of an entry.
+    fn get_cache_mut(&mut self, key: &K) -> Option<&mut V>;
 
-    /// Remove an entry from the map.
-    fn remove_cache(&mut self, key: &K);
+    /// Remove an entry from the map, returning its value if it was present.
+    fn remove_cache(&mut self, key: &K) -> Option<V>;
 }
 
 impl<K, V> CacheMap<K, V> for HashMap<K, V>
 where
     K: Hash + Eq,
 {
-    fn insert_cache(&mut self, key: K, value: V) {
-        self.insert(key, value);
+    fn insert_cache(&mut self, key: K, value: V) -> Option<V> {
+        self.insert(key, value)
     }
 
-    fn remove_cache(&mut self, key: &K) {
-        self.remove(key);
+    fn get_cache(&self, key: &K) -> Option<&V> {
+        self.get(key)
+    }
+
+    fn get_cache_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.get_mut(key)
+    }
+
+    fn remove_cache(&mut self, key: &K) -> Option<V> {
+        self.remove(key)
     }
 }
 
@@ -38,11 +60,19 @@ impl<K, V> CacheMap<K, V> for BTreeMap<K, V>
 where
     K: Ord,
 {
-    fn insert_cache(&mut self, key: K, value: V) {
-        self.insert(key, value);
+    fn insert_cache(&mut self, key: K, value: V) -> Option<V> {
+        self.insert(key, value)
+    }
+
+    fn get_cache(&self, key: &K) -> Option<&V> {
+        self.get(key)
+    }
+
+    fn get_cache_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.get_mut(key)
     }
 
-    fn remove_cache(&mut self, key: &K) {
-        self.remove(key);
+    fn remove_cache(&mut self, key: &K) -> Option<V> {
+        self.remove(key)
     }
 }
@@ -0,0 +1,233 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time::Instant,
+};
+
+/// Creates an expiration queue and the sender used to update it.
+///
+/// The origin must not be after the instants at which entries expire.
+pub(crate) fn channel<K>(origin: Instant) -> (ExpireSender<K>, ExpireQueue<K>) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let pending = Arc::new(AtomicUsize::new(0));
+
+    let sender = ExpireSender {
+        sender,
+        pending: pending.clone(),
+        next_generation: AtomicU64::new(0),
+        next_expiration: AtomicU64::new(NO_EXPIRATION),
+        origin,
+    };
+    let queue = ExpireQueue {
+        entries: BTreeMap::new(),
+        receiver,
+        pending,
+    };
+
+    (sender, queue)
+}
+
+/// Value of [`ExpireSender::next_expiration`] when no entry is queued.
+const NO_EXPIRATION: u64 = u64::MAX;
+
+/// Number of pending updates past which writers apply the updates of the
+/// queue themselves.
+pub(crate) const MAX_PENDING_RECORDS: usize = 1024;
+
+/// Update of the expiration queue sent by writers.
+#[derive(Debug)]
+enum Record<K> {
+    /// A new entry has been queued.
+    Push(ExpireKey, K),
+    /// A queued entry has been removed.
+    Remove(ExpireKey),
+}
+
+/// Sender used by writers to update the [`ExpireQueue`] without locking it.
+///
+/// Updates are sent through a channel and applied in order when the queue
+/// receives them. The sender also tracks the next expiration of the queue, so
+/// that writers know whether the expiration task must be woken up.
+#[derive(Debug)]
+pub(crate) struct ExpireSender<K> {
+    /// Sender of queue updates.
+    sender: UnboundedSender<Record<K>>,
+    /// Number of updates sent but not yet received, shared with the queue.
+    pending: Arc<AtomicUsize>,
+    /// Generation of the next queued entry.
+    next_generation: AtomicU64,
+    /// Next expiration of the queue, in nanoseconds since `origin`.
+    next_expiration: AtomicU64,
+    /// Origin of `next_expiration`.
+    origin: Instant,
+}
+
+impl<K> ExpireSender<K> {
+    /// Push a new entry in the queue, returning its position.
+    pub(crate) fn push(&self, key: K, expires_at: Instant) -> ExpireKey {
+        let expire_key = ExpireKey {
+            expires_at,
+            generation: self.next_generation.fetch_add(1, Ordering::Relaxed),
+        };
+
+        self.next_expiration
+            .fetch_min(self.to_nanos(expires_at), Ordering::Relaxed);
+
+        self.send(Record::Push(expire_key, key));
+
+        expire_key
+    }
+
+    /// Remove an entry from the queue.
+    pub(crate) fn remove(&self, expire_key: ExpireKey) {
+        self.send(Record::Remove(expire_key));
+    }
+
+    /// Returns the number of updates that have not been received by the
+    /// queue yet.
+    pub(crate) fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether enough updates are pending for writers to apply them.
+    pub(crate) fn has_backlog(&self) -> bool {
+        self.pending() >= MAX_PENDING_RECORDS
+    }
+
+    /// Sends an update to the queue.
+    fn send(&self, record: Record<K>) {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+
+        // Sending only fails if the queue has been dropped.
+        let _ = self.sender.send(record);
+    }
+
+    /// Returns when the next entry expires.
+    ///
+    /// This may be earlier than the actual next expiration if entries have
+    /// been removed since the queue last received updates.
+    pub(crate) fn next_expiration(&self) -> Option<Instant> {
+        match self.next_expiration.load(Ordering::Relaxed) {
+            NO_EXPIRATION => None,
+            nanos => Some(self.origin + Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// Sets the next expiration to the one of the given queue.
+    ///
+    /// The queue must have received all updates, and no update must be sent
+    /// concurrently.
+    pub(crate) fn sync(&self, queue: &ExpireQueue<K>) {
+        let nanos = queue
+            .next_expiration()
+            .map_or(NO_EXPIRATION, |expires_at| self.to_nanos(expires_at));
+
+        self.next_expiration.store(nanos, Ordering::Relaxed);
+    }
+
+    /// Converts an instant to nanoseconds since the origin.
+    fn to_nanos(&self, instant: Instant) -> u64 {
+        let nanos = instant.saturating_duration_since(self.origin).as_nanos();
+
+        nanos.min(u128::from(NO_EXPIRATION - 1)) as u64
+    }
+}
+
+/// Expiration queue of an [`AsyncTtl`] cache.
+///
+/// Entries are ordered by expiration instant. Each insertion is assigned an
+/// increasing generation number used to distinguish entries expiring at the
+/// same instant.
+///
+/// Writers update the queue through its [`ExpireSender`], and updates are only
+/// applied when the queue [receives] them.
+///
+/// [`AsyncTtl`]: crate::AsyncTtl
+/// [receives]: Self::receive
+pub(crate) struct ExpireQueue<K> {
+    /// Queued keys, indexed by expiration instant and generation.
+    entries: BTreeMap<ExpireKey, K>,
+    /// Receiver of queue updates.
+    receiver: UnboundedReceiver<Record<K>>,
+    /// Number of updates sent but not yet received, shared with the sender.
+    pending: Arc<AtomicUsize>,
+}
+
+impl<K> ExpireQueue<K> {
+    /// Applies the updates sent to the queue.
+    pub(crate) fn receive(&mut self) {
+        while let Ok(record) = self.receiver.try_recv() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+
+            match record {
+                Record::Push(expire_key, key) => {
+                    self.entries.insert(expire_key, key);
+                }
+                Record::Remove(expire_key) => {
+                    self.entries.remove(&expire_key);
+                }
+            }
+        }
+    }
+
+    /// Remove all entries from the queue.
+    pub(crate) fn clear(&mut self) {
+        self.receive();
+        self.entries.clear();
+    }
+
+    /// Returns the number of entries in the queue.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Remove all entries from the queue, returning their keys.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = K> {
+        self.receive();
+
+        std::mem::take(&mut self.entries).into_values()
+    }
+
+    /// Returns when the next entry expires.
+    pub(crate) fn next_expiration(&self) -> Option<Instant> {
+        self.entries.keys().next().map(|key| key.expires_at)
+    }
+
+    /// Remove and returns the next entry if it has expired.
+    pub(crate) fn pop_expired(&mut self, now: Instant) -> Option<(ExpireKey, K)> {
+        let entry = self.entries.first_entry()?;
+
+        if entry.key().expires_at <= now {
+            Some(entry.remove_entry())
+        } else {
+            None
+        }
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for ExpireQueue<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExpireQueue")
+            .field("entries", &self.entries)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Position of an entry in the [`ExpireQueue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct ExpireKey {
+    /// Instant at which the entry was scheduled to expire.
+    expires_at: Instant,
+    /// Generation of the queued entry.
+    generation: u64,
+}
@@ -0,0 +1,385 @@
+use std::{
+    fmt,
+    future::Future,
+    hash::{BuildHasher, Hash, RandomState},
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use tokio::{
+    runtime::Handle,
+    sync::{broadcast, watch, Notify, RwLockReadGuard},
+    time::Instant,
+};
+
+use crate::{
+    config::AsyncTtlConfig,
+    shutdown::{ShutdownHandle, ShutdownMode},
+    task::{self, Shards},
+    AsyncTtl, CacheEntry, CacheEvent, CacheMap, CacheStats, Reaped, ShardedCache,
+};
+
+/// Async cache with TTL, split into independently locked shards.
+///
+/// This type provides the same operations as [`AsyncTtl`], but keys are
+/// hashed into a fixed number of shards, each with its own locks and
+/// expiration queue. Concurrent writes to different shards do not wait for
+/// each other. A single [`ShardedExpireTask`] expires the entries of all the
+/// shards.
+///
+/// The shards share the weigher, eviction listener, events and statistics of
+/// the cache. The `max_capacity` and `max_weight` options of the
+/// configuration are split between the shards, and least recently used
+/// entries are evicted per shard. The limits of the shards add up to the
+/// configured values, so the cache never holds more entries or weight than
+/// configured, but a shard may evict entries while others are not full. The
+/// number of shards is reduced to `max_capacity` and `max_weight` if they are
+/// lower.
+///
+/// Unlike [`AsyncTtl`], this type has no `read` method: the entries are split
+/// between the maps of the shards, which are locked independently, so there
+/// is no single map to lock and return.
+///
+/// See the [crate] documentation to learn more.
+pub struct ShardedAsyncTtl<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    /// Shards of the cache.
+    shards: Box<[AsyncTtl<T, K, V>]>,
+    /// Hasher used to select the shard of a key.
+    hasher: RandomState,
+}
+
+impl<T, K, V> ShardedAsyncTtl<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone + Hash,
+{
+    /// Initialize a new [`ShardedAsyncTtl`] cache with the given number of
+    /// shards.
+    ///
+    /// The cache has at least one shard, and at most as many shards as its
+    /// maximum capacity and weight. This method returns the cache wrapped in
+    /// an [`Arc`] and the expiration task.
+    pub fn new(config: AsyncTtlConfig, shards: usize) -> (Arc<Self>, ShardedExpireTask<T, K, V>) {
+        AsyncTtl::builder(config).build_sharded(shards)
+    }
+
+    pub(crate) fn from_shards(shards: Vec<AsyncTtl<T, K, V>>) -> Self {
+        Self {
+            shards: shards.into_boxed_slice(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Returns the number of shards of the cache.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the statistics of the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.shards[0].stats()
+    }
+
+    /// Returns the total weight of the entries in the cache.
+    ///
+    /// Without [`Weigher`], this is the number of entries.
+    ///
+    /// [`Weigher`]: crate::Weigher
+    pub fn weight(&self) -> u64 {
+        self.shards.iter().map(AsyncTtl::weight).sum()
+    }
+
+    /// Subscribes to the events of the cache.
+    ///
+    /// See [`AsyncTtl::subscribe`].
+    pub fn subscribe(&self) -> broadcast::Receiver<CacheEvent<K>> {
+        self.shards[0].subscribe()
+    }
+
+    /// Returns a read-only access to the value of an entry.
+    ///
+    /// Only the shard of the key is locked for reading as long as the
+    /// returned guard is alive. See [`AsyncTtl::get`].
+    pub async fn get(&self, key: &K) -> Option<RwLockReadGuard<'_, V>> {
+        self.shard(key).get(key).await
+    }
+
+    /// Returns a clone of the value of an entry.
+    ///
+    /// See [`AsyncTtl::get_cloned`].
+    pub async fn get_cloned(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.shard(key).get_cloned(key).await
+    }
+
+    /// Returns whether the cache contains an entry for the given key.
+    ///
+    /// See [`AsyncTtl::contains_key`].
+    pub async fn contains_key(&self, key: &K) -> bool {
+        self.shard(key).contains_key(key).await
+    }
+
+    /// Returns the value of an entry, loading it if it is not present.
+    ///
+    /// See [`AsyncTtl::get_or_insert_with`].
+    pub async fn get_or_insert_with<F, Fut>(&self, key: K, init: F) -> V
+    where
+        K: Eq,
+        V: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        self.shard(&key).get_or_insert_with(key, init).await
+    }
+
+    /// Returns the value of an entry, loading it with a fallible loader if it
+    /// is not present.
+    ///
+    /// See [`AsyncTtl::try_get_or_insert_with`].
+    pub async fn try_get_or_insert_with<F, Fut, E>(&self, key: K, init: F) -> Result<V, E>
+    where
+        K: Eq,
+        V: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+        E: Clone + Send + Sync + 'static,
+    {
+        self.shard(&key).try_get_or_insert_with(key, init).await
+    }
+
+    /// Inserts a new entry into the cache.
+    ///
+    /// See [`AsyncTtl::insert`].
+    pub async fn insert(&self, key: K, value: V) {
+        self.shard(&key).insert(key, value).await
+    }
+
+    /// Inserts a new entry into the cache with a custom time-to-live.
+    ///
+    /// See [`AsyncTtl::insert_with_ttl`].
+    pub async fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        self.shard(&key).insert_with_ttl(key, value, ttl).await
+    }
+
+    /// Inserts a new entry into the cache that expires at the given instant.
+    ///
+    /// See [`AsyncTtl::insert_until`].
+    pub async fn insert_until(&self, key: K, value: V, expires_at: Instant) {
+        self.shard(&key).insert_until(key, value, expires_at).await
+    }
+
+    /// Removes an entry from the cache, returning its value if it was present.
+    pub async fn remove(&self, key: &K) -> Option<V> {
+        self.shard(key).remove(key).await
+    }
+
+    /// Removes multiple entries from the cache.
+    ///
+    /// The returned values are in the same order as the provided keys, with
+    /// `None` for keys that were not present. Unlike [`AsyncTtl::remove_many`],
+    /// entries are removed one by one.
+    pub async fn remove_many<'a, I>(&self, keys: I) -> Vec<Option<V>>
+    where
+        I: IntoIterator<Item = &'a K>,
+        K: 'a,
+    {
+        let mut values = Vec::new();
+        for key in keys {
+            values.push(self.remove(key).await);
+        }
+
+        values
+    }
+
+    /// Removes all entries from the cache.
+    ///
+    /// Shards are cleared one after the other.
+    pub async fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.clear().await;
+        }
+    }
+
+    /// Returns the shard of a key.
+    fn shard(&self, key: &K) -> &AsyncTtl<T, K, V> {
+        let index = self.hasher.hash_one(key) % self.shards.len() as u64;
+
+        &self.shards[index as usize]
+    }
+}
+
+impl<T, K, V> ShardedAsyncTtl<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default + Send + Sync + 'static,
+    K: Clone + Hash + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    /// Initialize a new [`ShardedAsyncTtl`] cache with the given number of
+    /// shards and spawns its expiration task on the current [tokio] runtime.
+    ///
+    /// The expiration task is aborted once all the clones of the returned
+    /// [`ShardedCache`] have been dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a [tokio] runtime.
+    pub fn spawn(config: AsyncTtlConfig, shards: usize) -> ShardedCache<T, K, V> {
+        AsyncTtl::builder(config).spawn_sharded(shards)
+    }
+
+    /// Initialize a new [`ShardedAsyncTtl`] cache with the given number of
+    /// shards and spawns its expiration task on the runtime of the given
+    /// [`Handle`].
+    ///
+    /// The expiration task is aborted once all the clones of the returned
+    /// [`ShardedCache`] have been dropped.
+    pub fn spawn_on(
+        config: AsyncTtlConfig,
+        shards: usize,
+        handle: &Handle,
+    ) -> ShardedCache<T, K, V> {
+        AsyncTtl::builder(config).spawn_sharded_on(shards, handle)
+    }
+}
+
+impl<T, K, V> Shards<T, K, V> for ShardedAsyncTtl<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    fn shards(&self) -> &[AsyncTtl<T, K, V>] {
+        &self.shards
+    }
+}
+
+impl<T, K, V> fmt::Debug for ShardedAsyncTtl<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default + fmt::Debug,
+    K: Clone + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardedAsyncTtl")
+            .field("shards", &self.shards)
+            .finish_non_exhaustive()
+    }
+}
+
+/// [`ShardedAsyncTtl`] expiration task.
+///
+/// This type represent the expiration task of all the shards of a cache and
+/// must be started to ensure expired keys are removed. It behaves like
+/// [`AsyncTtlExpireTask`].
+///
+/// [`AsyncTtlExpireTask`]: crate::AsyncTtlExpireTask
+#[derive(Debug, Clone)]
+pub struct ShardedExpireTask<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    cache: Weak<ShardedAsyncTtl<T, K, V>>,
+    wakeup: Arc<Notify>,
+    shutdown: Arc<watch::Sender<Option<ShutdownMode>>>,
+}
+
+impl<T, K, V> ShardedExpireTask<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    /// Initialize a new [`ShardedExpireTask`].
+    pub fn new(cache: Arc<ShardedAsyncTtl<T, K, V>>) -> Self {
+        Self {
+            wakeup: cache.shards[0].wakeup.clone(),
+            cache: Arc::downgrade(&cache),
+            shutdown: Arc::new(watch::Sender::new(None)),
+        }
+    }
+
+    /// Returns a handle used to shut down the task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(self.shutdown.clone())
+    }
+
+    /// Start the cache expiration task.
+    ///
+    /// See [`AsyncTtlExpireTask::run`].
+    ///
+    /// [`AsyncTtlExpireTask::run`]: crate::AsyncTtlExpireTask::run
+    pub async fn run(&self) {
+        task::run(&self.cache, &self.wakeup, &self.shutdown).await
+    }
+
+    /// Removes the entries of all the shards that have expired, without
+    /// waiting.
+    ///
+    /// See [`AsyncTtlExpireTask::reap_once`].
+    ///
+    /// [`AsyncTtlExpireTask::reap_once`]: crate::AsyncTtlExpireTask::reap_once
+    pub async fn reap_once(&self) -> Reaped {
+        task::reap_once(&self.cache).await
+    }
+
+    /// Returns when expired entries should next be removed.
+    ///
+    /// This is the earliest deadline of the shards. See
+    /// [`AsyncTtlExpireTask::next_deadline`].
+    ///
+    /// [`AsyncTtlExpireTask::next_deadline`]: crate::AsyncTtlExpireTask::next_deadline
+    pub fn next_deadline(&self) -> Option<Instant> {
+        task::next_deadline(&*self.cache.upgrade()?)
+    }
+
+    /// Runs a single cycle of the task without waiting.
+    ///
+    /// Returns whether the task would keep running.
+    #[cfg(feature = "testing")]
+    pub(crate) async fn step(&self) -> bool {
+        task::step(&self.cache, &self.shutdown).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    type TestMap = HashMap<u32, CacheEntry<u32>>;
+    type TestCache = ShardedAsyncTtl<TestMap, u32, u32>;
+
+    #[tokio::test]
+    async fn shard_limits_add_up_to_configured_limits() {
+        let config = AsyncTtlConfig::builder(Duration::from_secs(60))
+            .max_capacity(10)
+            .max_weight(7)
+            .build();
+        let (cache, _task) = TestCache::new(config, 4);
+
+        let capacities = cache.shards.iter().map(|shard| shard.config.max_capacity);
+        assert_eq!(capacities.flatten().sum::<usize>(), 10);
+        let weights = cache.shards.iter().map(|shard| shard.config.max_weight);
+        assert_eq!(weights.flatten().sum::<u64>(), 7);
+    }
+
+    #[tokio::test]
+    async fn shard_count_is_reduced_to_max_capacity() {
+        let config = AsyncTtlConfig::builder(Duration::from_secs(60))
+            .max_capacity(2)
+            .build();
+        let (cache, _task) = TestCache::new(config, 8);
+        assert_eq!(cache.shard_count(), 2);
+
+        for key in 0..32 {
+            cache.insert(key, key).await;
+            assert!(cache.weight() <= 2);
+        }
+        assert_eq!(cache.weight(), 2);
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Behavior of the expiration task when it is shut down.
+///
+/// See [`ShutdownHandle::shutdown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMode {
+    /// Stop the task without removing any entry.
+    Immediate,
+    /// Remove the entries that have expired, then stop the task.
+    Reap,
+    /// Remove all entries, expired or not, then stop the task.
+    ///
+    /// Removed entries are sent to the eviction listener with the
+    /// [`RemovalCause::Explicit`] cause.
+    ///
+    /// [`RemovalCause::Explicit`]: crate::RemovalCause::Explicit
+    Drain,
+}
+
+/// Handle used to shut down an [`AsyncTtlExpireTask`].
+///
+/// This handle is returned by [`AsyncTtlExpireTask::shutdown_handle`].
+///
+/// [`AsyncTtlExpireTask`]: crate::AsyncTtlExpireTask
+/// [`AsyncTtlExpireTask::shutdown_handle`]: crate::AsyncTtlExpireTask::shutdown_handle
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<Option<ShutdownMode>>>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(sender: Arc<watch::Sender<Option<ShutdownMode>>>) -> Self {
+        Self { sender }
+    }
+
+    /// Shuts down the expiration task.
+    ///
+    /// The task stops between two expiration batches, after running the
+    /// final step of the given [`ShutdownMode`]. The [`run`] method returns
+    /// once the task has stopped.
+    ///
+    /// Only the first call to this method is taken into account.
+    ///
+    /// [`run`]: crate::AsyncTtlExpireTask::run
+    pub fn shutdown(&self, mode: ShutdownMode) {
+        self.sender.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+
+            *current = Some(mode);
+            true
+        });
+    }
+
+    /// Returns whether the expiration task has been shut down.
+    pub fn is_shutdown(&self) -> bool {
+        self.sender.borrow().is_some()
+    }
+}
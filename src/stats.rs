@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Statistics of an [`AsyncTtl`] cache.
+///
+/// Statistics are returned by [`AsyncTtl::stats`].
+///
+/// [`AsyncTtl`]: crate::AsyncTtl
+/// [`AsyncTtl::stats`]: crate::AsyncTtl::stats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of entries that expired because they reached their maximum
+    /// age or time-to-live.
+    pub age_expirations: u64,
+    /// Number of entries that expired because they were not accessed during
+    /// their idle timeout.
+    pub idle_expirations: u64,
+    /// Number of entries evicted because the cache reached its maximum
+    /// capacity or weight.
+    pub capacity_evictions: u64,
+}
+
+/// Counters used to compute [`CacheStats`].
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounter {
+    age_expirations: AtomicU64,
+    idle_expirations: AtomicU64,
+    capacity_evictions: AtomicU64,
+}
+
+impl StatsCounter {
+    /// Records the expiration of an entry.
+    pub(crate) fn record_expiration(&self, idle: bool) {
+        let counter = if idle {
+            &self.idle_expirations
+        } else {
+            &self.age_expirations
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the eviction of an entry because of the cache capacity.
+    pub(crate) fn record_capacity_eviction(&self) {
+        self.capacity_evictions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the counters.
+    pub(crate) fn snapshot(&self) -> CacheStats {
+        CacheStats {
+            age_expirations: self.age_expirations.load(Ordering::Relaxed),
+            idle_expirations: self.idle_expirations.load(Ordering::Relaxed),
+            capacity_evictions: self.capacity_evictions.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    future::{poll_fn, Future},
+    pin::pin,
+    slice,
+    sync::{Arc, Weak},
+    task::Poll,
+};
+
+use tokio::{
+    sync::{watch, Notify},
+    task,
+    time::Instant,
+};
+
+use crate::{
+    deadline,
+    locked::LockedCache,
+    shutdown::{ShutdownHandle, ShutdownMode},
+    AsyncTtl, CacheEntry, CacheMap,
+};
+
+/// [`AsyncTtl`] expiration task.
+///
+/// This type represent the expiration task of a cache and must be started
+/// to ensure expired keys are removed, unless the cache is configured to
+/// remove them lazily.
+///
+/// See the [crate] documentation to learn more.
+#[derive(Debug, Clone)]
+pub struct AsyncTtlExpireTask<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    cache: Weak<AsyncTtl<T, K, V>>,
+    wakeup: Arc<Notify>,
+    shutdown: Arc<watch::Sender<Option<ShutdownMode>>>,
+}
+
+impl<T, K, V> AsyncTtlExpireTask<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    /// Initialize a new [`AsyncTtlExpireTask`].
+    pub fn new(cache: Arc<AsyncTtl<T, K, V>>) -> Self {
+        Self {
+            wakeup: cache.wakeup.clone(),
+            cache: Arc::downgrade(&cache),
+            shutdown: Arc::new(watch::Sender::new(None)),
+        }
+    }
+
+    /// Returns a handle used to shut down the task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(self.shutdown.clone())
+    }
+
+    /// Start the cache expiration task.
+    ///
+    /// This task will automatically expire cached values based on the provided
+    /// configuration. It contains an infinite loop so you should start it in a
+    /// new [tokio] task.
+    ///
+    /// The loop stops when the task is shut down with its [`ShutdownHandle`],
+    /// or when all the references to the cache have been dropped.
+    pub async fn run(&self) {
+        run(&self.cache, &self.wakeup, &self.shutdown).await
+    }
+
+    /// Removes the entries that have expired, without waiting.
+    ///
+    /// This runs a single iteration of the loop of [`run`], so that the
+    /// expiration can be driven by another scheduler instead of running the
+    /// task. Entries are removed in batches of `max_evictions_per_tick`, and
+    /// the returned [`Reaped`] reports how many entries were removed and when
+    /// this method should be called next.
+    ///
+    /// This method does not check whether the task has been shut down.
+    ///
+    /// [`run`]: Self::run
+    pub async fn reap_once(&self) -> Reaped {
+        reap_once(&self.cache).await
+    }
+
+    /// Returns when expired entries should next be removed.
+    ///
+    /// This is the next expiration of the cache plus the `delta_delay` of its
+    /// configuration, or `None` if the cache is empty or has been dropped. It
+    /// may be earlier than needed if entries have been removed since the last
+    /// removal of expired entries.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        next_deadline(&*self.cache.upgrade()?)
+    }
+
+    /// Runs a single cycle of the task without waiting.
+    ///
+    /// Returns whether the task would keep running.
+    #[cfg(feature = "testing")]
+    pub(crate) async fn step(&self) -> bool {
+        step(&self.cache, &self.shutdown).await
+    }
+}
+
+/// Outcome of [`AsyncTtlExpireTask::reap_once`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reaped {
+    /// Number of expired entries removed from the cache.
+    pub removed: usize,
+    /// When expired entries should next be removed, or `None` if the cache is
+    /// empty or has been dropped.
+    ///
+    /// See [`AsyncTtlExpireTask::next_deadline`].
+    pub next_deadline: Option<Instant>,
+}
+
+/// Cache made of one or more [`AsyncTtl`] shards, expired by a single task.
+pub(crate) trait Shards<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    /// Returns the shards of the cache.
+    fn shards(&self) -> &[AsyncTtl<T, K, V>];
+}
+
+impl<T, K, V> Shards<T, K, V> for AsyncTtl<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    fn shards(&self) -> &[AsyncTtl<T, K, V>] {
+        slice::from_ref(self)
+    }
+}
+
+/// Runs the expiration loop of a cache until it is shut down or dropped.
+pub(crate) async fn run<C, T, K, V>(
+    cache: &Weak<C>,
+    wakeup: &Notify,
+    shutdown: &watch::Sender<Option<ShutdownMode>>,
+) where
+    C: Shards<T, K, V>,
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    let mut shutdown = shutdown.subscribe();
+
+    loop {
+        let mode = *shutdown.borrow_and_update();
+        if let Some(mode) = mode {
+            stop(cache, mode).await;
+            return;
+        }
+
+        // Get next expiration time
+        let (clock, wake_at) = {
+            // Explicit scope to ensure the cache is dropped while waiting
+            let Some(cache) = cache.upgrade() else {
+                return;
+            };
+
+            // The shards of a cache share the same configuration and clock.
+            let shard = &cache.shards()[0];
+            let clock = shard.clock.clone();
+            let wake_at = next_deadline(&*cache).or_else(|| {
+                let empty_delay = shard.config.empty_delay?;
+                Some(deadline(clock.now(), empty_delay))
+            });
+
+            (clock, wake_at)
+        };
+
+        // Wait for the next expiration, until an entry that expires
+        // earlier is inserted, until the cache is dropped or until the
+        // task is shut down.
+        let notified = wakeup.notified();
+        match wake_at {
+            Some(wake_at) => {
+                let wait = race(clock.sleep_until(wake_at), notified);
+                race(wait, shutdown.changed()).await
+            }
+            None => race(notified, shutdown.changed()).await,
+        }
+
+        let Some(cache) = cache.upgrade() else {
+            return;
+        };
+        expire(cache.shards(), clock.now(), Some(&shutdown)).await;
+    }
+}
+
+/// Returns when the expired entries of a cache should next be removed.
+pub(crate) fn next_deadline<C, T, K, V>(cache: &C) -> Option<Instant>
+where
+    C: Shards<T, K, V>,
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    let shards = cache.shards();
+
+    let next_expiration = shards
+        .iter()
+        .filter_map(|shard| shard.expirations.next_expiration())
+        .min()?;
+
+    Some(deadline(next_expiration, shards[0].config.delta_delay))
+}
+
+/// Removes the expired entries of a cache and returns the next deadline.
+pub(crate) async fn reap_once<C, T, K, V>(cache: &Weak<C>) -> Reaped
+where
+    C: Shards<T, K, V>,
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    let Some(cache) = cache.upgrade() else {
+        return Reaped::default();
+    };
+    let now = cache.shards()[0].clock.now();
+
+    Reaped {
+        removed: expire(cache.shards(), now, None).await,
+        next_deadline: next_deadline(&*cache),
+    }
+}
+
+/// Runs a single cycle of the expiration loop without waiting.
+///
+/// This is [`reap_once`] preceded by the shutdown check of the loop. Returns
+/// whether the loop would keep running, which is not the case once the task
+/// has been shut down or the cache dropped.
+#[cfg(feature = "testing")]
+pub(crate) async fn step<C, T, K, V>(
+    cache: &Weak<C>,
+    shutdown: &watch::Sender<Option<ShutdownMode>>,
+) -> bool
+where
+    C: Shards<T, K, V>,
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    let mode = *shutdown.borrow();
+    if let Some(mode) = mode {
+        stop(cache, mode).await;
+        return false;
+    }
+
+    reap_once(cache).await;
+
+    cache.strong_count() > 0
+}
+
+/// Runs the final step of the task before it stops.
+async fn stop<C, T, K, V>(cache: &Weak<C>, mode: ShutdownMode)
+where
+    C: Shards<T, K, V>,
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    let Some(cache) = cache.upgrade() else {
+        return;
+    };
+
+    match mode {
+        ShutdownMode::Immediate => {}
+        ShutdownMode::Reap => {
+            let now = cache.shards()[0].clock.now();
+            expire(cache.shards(), now, None).await;
+        }
+        ShutdownMode::Drain => {
+            for shard in cache.shards() {
+                shard.clear().await;
+            }
+        }
+    }
+}
+
+/// Removes the entries of the shards that have expired at the given instant,
+/// returning the number of removed entries.
+///
+/// If a shutdown receiver is given, the removal stops after the current batch
+/// once the task has been shut down.
+async fn expire<T, K, V>(
+    shards: &[AsyncTtl<T, K, V>],
+    now: Instant,
+    shutdown: Option<&watch::Receiver<Option<ShutdownMode>>>,
+) -> usize
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    let is_shutdown = || shutdown.is_some_and(|shutdown| shutdown.borrow().is_some());
+    let mut removed = 0;
+
+    for shard in shards {
+        // Shards without expired entries are not locked.
+        let next_expiration = shard.expirations.next_expiration();
+        if next_expiration.is_none_or(|expires_at| expires_at > now) {
+            continue;
+        }
+
+        loop {
+            // Most updates of the queue are received before locking the
+            // cache, so writers are not blocked while they are applied.
+            let mut expires = shard.expires.lock().await;
+            expires.receive();
+
+            let mut locked = LockedCache::lock(shard).await;
+            removed += locked.expire(&mut expires, now, shard.config.max_evictions_per_tick);
+            let remaining = expires
+                .next_expiration()
+                .is_some_and(|expires_at| expires_at <= now);
+            let entries = locked.unlock_with(expires);
+
+            shard.notify(entries).await;
+
+            if is_shutdown() {
+                return removed;
+            }
+            if !remaining {
+                break;
+            }
+
+            // Let other tasks access the cache before the next batch.
+            task::yield_now().await;
+        }
+    }
+
+    removed
+}
+
+/// Waits until one of the two futures completes.
+async fn race(first: impl Future, second: impl Future) {
+    let mut first = pin!(first);
+    let mut second = pin!(second);
+
+    poll_fn(|cx| {
+        if first.as_mut().poll(cx).is_ready() || second.as_mut().poll(cx).is_ready() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+    use tokio::time;
+
+    use super::*;
+    use crate::{config::AsyncTtlConfig, Clock, ManualClock, RemovalCause};
+
+    type TestCache = AsyncTtl<HashMap<u32, CacheEntry<u32>>, u32, u32>;
+
+    #[tokio::test(start_paused = true)]
+    async fn immediate_shutdown_stops_without_removing_entries() {
+        let (cache, task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(1)));
+
+        cache.insert(1, 1).await;
+        time::advance(Duration::from_secs(2)).await;
+        task.shutdown_handle().shutdown(ShutdownMode::Immediate);
+        task.run().await;
+
+        assert!(cache.read().await.get_cache(&1).is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reap_shutdown_removes_expired_entries_and_stops() {
+        let (cache, task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(1)));
+        let shutdown = task.shutdown_handle();
+
+        cache.insert(1, 1).await;
+        cache.insert_with_ttl(2, 2, Duration::from_secs(60)).await;
+        time::advance(Duration::from_secs(2)).await;
+        shutdown.shutdown(ShutdownMode::Reap);
+        task.run().await;
+
+        assert!(shutdown.is_shutdown());
+        assert!(cache.read().await.get_cache(&1).is_none());
+        assert!(cache.contains_key(&2).await);
+    }
+
+    #[tokio::test]
+    async fn drain_shutdown_sends_all_entries_to_listener() {
+        let removed = Arc::new(Mutex::new(Vec::new()));
+        let listener = removed.clone();
+        let (cache, task) = TestCache::builder(AsyncTtlConfig::new(Duration::from_secs(60)))
+            .eviction_listener(move |key, _, cause| listener.lock().unwrap().push((key, cause)))
+            .build();
+        let shutdown = task.shutdown_handle();
+        let task = tokio::spawn(async move { task.run().await });
+
+        cache.insert(1, 1).await;
+        cache.insert(2, 2).await;
+        shutdown.shutdown(ShutdownMode::Drain);
+        task.await.unwrap();
+
+        let mut removed = removed.lock().unwrap().clone();
+        removed.sort_by_key(|(key, _)| *key);
+        assert_eq!(
+            removed,
+            [(1, RemovalCause::Explicit), (2, RemovalCause::Explicit)]
+        );
+        assert_eq!(cache.weight(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn task_stops_once_cache_is_dropped() {
+        let (cache, task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(60)));
+        let task = tokio::spawn(async move { task.run().await });
+
+        cache.insert(1, 1).await;
+        let weak = Arc::downgrade(&cache);
+        drop(cache);
+
+        time::timeout(Duration::from_secs(120), task)
+            .await
+            .expect("the task should stop once the cache is dropped")
+            .unwrap();
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn parked_task_wakes_up_when_an_entry_is_inserted() {
+        let (cache, task) = TestCache::new(AsyncTtlConfig::new(Duration::from_secs(1)));
+        tokio::spawn(async move { task.run().await });
+
+        // The task waits without timeout while the queue is empty.
+        time::sleep(Duration::from_secs(3600)).await;
+        cache.insert(1, 1).await;
+        time::sleep(Duration::from_secs(2)).await;
+
+        assert!(cache.read().await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn immediate_shutdown_stops_between_batches() {
+        let config = AsyncTtlConfig::builder(Duration::from_secs(1))
+            .max_evictions_per_tick(1)
+            .build();
+        let (cache, task) = TestCache::builder(config).build();
+        let shutdown = task.shutdown_handle();
+        let task = tokio::spawn(async move { task.run().await });
+
+        for key in 0..1000 {
+            cache.insert(key, key).await;
+        }
+        time::advance(Duration::from_secs(2)).await;
+
+        // Shut down the task once it has removed the first batch.
+        while cache.weight() == 1000 {
+            task::yield_now().await;
+        }
+        shutdown.shutdown(ShutdownMode::Immediate);
+        task.await.unwrap();
+
+        assert!(cache.weight() > 0);
+    }
+
+    #[tokio::test]
+    async fn reap_once_reports_removed_entries_and_next_deadline() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let config = AsyncTtlConfig::new(Duration::from_secs(10));
+        let delta_delay = config.delta_delay;
+        let (cache, task) = TestCache::builder(config).clock(clock.clone()).build();
+        assert_eq!(task.next_deadline(), None);
+
+        cache.insert(1, 1).await;
+        cache.insert_with_ttl(2, 2, Duration::from_secs(20)).await;
+        let deadline = start + Duration::from_secs(10) + delta_delay;
+        assert_eq!(task.next_deadline(), Some(deadline));
+
+        clock.advance(Duration::from_secs(15));
+        let reaped = task.reap_once().await;
+        assert_eq!(reaped.removed, 1);
+        let deadline = start + Duration::from_secs(20) + delta_delay;
+        assert_eq!(reaped.next_deadline, Some(deadline));
+
+        drop(cache);
+        assert_eq!(task.reap_once().await.removed, 0);
+        assert_eq!(task.next_deadline(), None);
+    }
+}
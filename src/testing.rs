@@ -0,0 +1,270 @@
+//! Helpers to test code using [`AsyncTtl`] caches.
+//!
+//! This module is enabled by the `testing` feature. It allows tests to
+//! control the expiration of entries without waiting for the real time to
+//! pass:
+//!
+//! - [`TestCache`] builds a cache with a [`ManualClock`] and keeps its
+//!   expiration task, so that expired entries are removed exactly when
+//!   [`TestCache::advance_and_reap`] is called.
+//! - [`step`] and [`step_sharded`] run a single cycle of an expiration task
+//!   instead of its infinite loop.
+//! - [`assert_expired`] and [`assert_not_expired`] check whether an entry has
+//!   been removed from a cache.
+
+use std::{fmt, ops::Deref, sync::Arc, time::Duration};
+
+use crate::{
+    config::AsyncTtlConfig, AsyncTtl, AsyncTtlBuilder, AsyncTtlExpireTask, CacheEntry, CacheMap,
+    ManualClock, ShardedExpireTask,
+};
+
+/// [`AsyncTtl`] cache driven by a [`ManualClock`].
+///
+/// The expiration task of the cache is not spawned: expired entries are only
+/// removed when the task is stepped with [`reap`] or [`advance_and_reap`].
+/// This handle dereferences to the [`AsyncTtl`] cache.
+///
+/// [`reap`]: Self::reap
+/// [`advance_and_reap`]: Self::advance_and_reap
+pub struct TestCache<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    cache: Arc<AsyncTtl<T, K, V>>,
+    task: AsyncTtlExpireTask<T, K, V>,
+    clock: ManualClock,
+}
+
+impl<T, K, V> TestCache<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    /// Initialize a new [`TestCache`] with the given configuration.
+    pub fn new(config: AsyncTtlConfig) -> Self {
+        Self::from_builder(AsyncTtl::builder(config))
+    }
+
+    /// Initialize a new [`TestCache`] from a configured builder.
+    ///
+    /// The clock set on the builder is replaced by a [`ManualClock`].
+    pub fn from_builder(builder: AsyncTtlBuilder<T, K, V>) -> Self {
+        let clock = ManualClock::new();
+        let (cache, task) = builder.clock(clock.clone()).build();
+
+        Self { cache, task, clock }
+    }
+
+    /// Returns the underlying cache.
+    pub fn as_arc(&self) -> &Arc<AsyncTtl<T, K, V>> {
+        &self.cache
+    }
+
+    /// Returns the expiration task of the cache.
+    ///
+    /// The task can be shut down through its [`ShutdownHandle`], which is
+    /// applied on the next call to [`reap`].
+    ///
+    /// [`ShutdownHandle`]: crate::ShutdownHandle
+    /// [`reap`]: Self::reap
+    pub fn task(&self) -> &AsyncTtlExpireTask<T, K, V> {
+        &self.task
+    }
+
+    /// Returns the clock of the cache.
+    pub fn clock(&self) -> &ManualClock {
+        &self.clock
+    }
+
+    /// Advances the clock of the cache without removing expired entries.
+    pub fn advance(&self, duration: Duration) {
+        self.clock.advance(duration);
+    }
+
+    /// Removes the entries that have expired at the current instant of the
+    /// clock, by running a single cycle of the expiration task.
+    ///
+    /// Returns whether the task would keep running.
+    pub async fn reap(&self) -> bool {
+        step(&self.task).await
+    }
+
+    /// Advances the clock of the cache, then removes the entries that have
+    /// expired.
+    ///
+    /// Returns whether the task would keep running.
+    pub async fn advance_and_reap(&self, duration: Duration) -> bool {
+        self.advance(duration);
+        self.reap().await
+    }
+}
+
+impl<T, K, V> Deref for TestCache<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    type Target = AsyncTtl<T, K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cache
+    }
+}
+
+impl<T, K, V> fmt::Debug for TestCache<T, K, V>
+where
+    T: CacheMap<K, CacheEntry<V>> + Default + fmt::Debug,
+    K: Clone + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestCache")
+            .field("cache", &self.cache)
+            .field("clock", &self.clock)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Runs a single cycle of an [`AsyncTtlExpireTask`] without waiting.
+///
+/// The entries that have expired at the current instant of the clock of the
+/// cache are removed, and the task is stopped if it has been shut down.
+/// Returns whether the task would keep running, which is not the case once
+/// it has been shut down or the cache dropped.
+pub async fn step<T, K, V>(task: &AsyncTtlExpireTask<T, K, V>) -> bool
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    task.step().await
+}
+
+/// Runs a single cycle of a [`ShardedExpireTask`] without waiting.
+///
+/// See [`step`].
+pub async fn step_sharded<T, K, V>(task: &ShardedExpireTask<T, K, V>) -> bool
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone,
+{
+    task.step().await
+}
+
+/// Asserts that the entry of a key has been removed from the cache.
+///
+/// Unlike [`AsyncTtl::contains_key`], this fails if the entry has expired
+/// but has not been removed by the expiration task yet.
+///
+/// # Panics
+///
+/// Panics if the cache contains an entry for the key.
+pub async fn assert_expired<T, K, V>(cache: &AsyncTtl<T, K, V>, key: &K)
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone + fmt::Debug,
+{
+    let data = cache.read().await;
+
+    assert!(
+        data.get_cache(key).is_none(),
+        "expected the entry of {key:?} to be expired and removed from the cache",
+    );
+}
+
+/// Asserts that the cache contains an entry for a key that has not expired.
+///
+/// # Panics
+///
+/// Panics if the cache does not contain an entry for the key, or if the
+/// entry has expired.
+pub async fn assert_not_expired<T, K, V>(cache: &AsyncTtl<T, K, V>, key: &K)
+where
+    T: CacheMap<K, CacheEntry<V>> + Default,
+    K: Clone + fmt::Debug,
+{
+    assert!(
+        cache.contains_key(key).await,
+        "expected the cache to contain an entry for {key:?} that has not expired",
+    );
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::ShutdownMode;
+
+    type TestMap = HashMap<u32, CacheEntry<u32>>;
+
+    fn test_cache() -> TestCache<TestMap, u32, u32> {
+        TestCache::new(AsyncTtlConfig::new(Duration::from_secs(60)))
+    }
+
+    #[tokio::test]
+    async fn advance_and_reap_removes_expired_entries() {
+        let cache = test_cache();
+        cache.insert(1, 1).await;
+        cache.insert_with_ttl(2, 2, Duration::from_secs(120)).await;
+
+        assert!(cache.advance_and_reap(Duration::from_secs(59)).await);
+        assert_not_expired(&cache, &1).await;
+
+        assert!(cache.advance_and_reap(Duration::from_secs(1)).await);
+        assert_expired(&cache, &1).await;
+        assert_not_expired(&cache, &2).await;
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "to be expired and removed")]
+    async fn assert_expired_fails_before_reaping() {
+        let cache = test_cache();
+        cache.insert(1, 1).await;
+        cache.advance(Duration::from_secs(60));
+
+        assert!(!cache.contains_key(&1).await);
+        assert_expired(&cache, &1).await;
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "that has not expired")]
+    async fn assert_not_expired_fails_after_expiration() {
+        let cache = test_cache();
+        cache.insert(1, 1).await;
+        cache.advance(Duration::from_secs(60));
+
+        assert_not_expired(&cache, &1).await;
+    }
+
+    #[tokio::test]
+    async fn step_stops_once_task_is_shut_down() {
+        let cache = test_cache();
+        cache.insert(1, 1).await;
+        cache.advance(Duration::from_secs(60));
+
+        cache.task().shutdown_handle().shutdown(ShutdownMode::Reap);
+        assert!(!step(cache.task()).await);
+        assert_expired(&cache, &1).await;
+    }
+
+    #[tokio::test]
+    async fn step_sharded_removes_expired_entries_of_all_shards() {
+        let clock = ManualClock::new();
+        let (cache, task) =
+            AsyncTtl::<TestMap, u32, u32>::builder(AsyncTtlConfig::new(Duration::from_secs(60)))
+                .clock(clock.clone())
+                .build_sharded(4);
+
+        for key in 0..16 {
+            cache.insert(key, key).await;
+        }
+        clock.advance(Duration::from_secs(60));
+
+        assert!(step_sharded(&task).await);
+        assert_eq!(cache.weight(), 0);
+
+        drop(cache);
+        assert!(!step_sharded(&task).await);
+    }
+}
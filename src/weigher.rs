@@ -0,0 +1,24 @@
+/// Weight of the entries of an [`AsyncTtl`] cache.
+///
+/// A weigher computes the weight of each inserted entry, which is used to
+/// bound the total weight of the cache with the `max_weight` option of the
+/// configuration. The weight usually approximates the memory usage of the
+/// entry.
+///
+/// This trait is implemented for closures taking the key and the value of the
+/// entry.
+///
+/// [`AsyncTtl`]: crate::AsyncTtl
+pub trait Weigher<K, V> {
+    /// Returns the weight of an entry.
+    fn weigh(&self, key: &K, value: &V) -> u64;
+}
+
+impl<K, V, F> Weigher<K, V> for F
+where
+    F: Fn(&K, &V) -> u64,
+{
+    fn weigh(&self, key: &K, value: &V) -> u64 {
+        self(key, value)
+    }
+}